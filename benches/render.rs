@@ -0,0 +1,85 @@
+//! Full frames of reference scenes, and lighting on its own.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use glam::Vec3;
+use topdown::{boxshape::BoxShape, camera::Camera, color::Color, drawable::Drawable, raster::View, scenefile::SceneFile, world::World};
+
+
+/// Builds a world with a render target of the given width and height.
+type Scene = fn(usize, usize) -> World;
+
+
+/// The scene the binary starts with.
+fn default_scene(width: usize, height: usize) -> World {
+    let scene = SceneFile::parse(include_str!("../scenes/default.json")).expect("built-in scene is valid");
+    World::from_scene(&scene, width, height).expect("built-in scene is valid")
+}
+
+/// Many small boxes on a floor, most of the work is in the number of objects.
+fn box_grid(width: usize, height: usize) -> World {
+    let mut world = World::new(width, height);
+    world.spawn_object(Box::new(BoxShape::new(Vec3::new(0.0, 5.0, 0.0), Vec3::new(240.0, 2.0, 240.0), Color::rgb(0.4, 0.4, 0.4))));
+    for x in 0..10 {
+        for z in 0..10 {
+            let pos = Vec3::new(x as f32 * 22.0 - 100.0, -4.0, z as f32 * 22.0 - 100.0);
+            world.spawn_object(Box::new(BoxShape::new(pos, Vec3::splat(10.0), Color::rgb(0.5, 0.7, 0.9))));
+        };
+    };
+    world.sync_index();
+    world
+}
+
+/// One large box close up, most of the work is in the number of points.
+fn dense(width: usize, height: usize) -> World {
+    let mut world = World::new(width, height);
+    world.spawn_object(Box::new(BoxShape::new(Vec3::ZERO, Vec3::splat(128.0), Color::rgb(0.9, 0.8, 0.6))));
+    world.sync_index();
+    world
+}
+
+fn draw(c: &mut Criterion) {
+    let scenes: [(&str, Scene); 3] = [("default", default_scene), ("box_grid", box_grid), ("dense", dense)];
+    let mut group = c.benchmark_group("draw");
+    for (name, build) in scenes {
+        for (width, height) in [(300, 200), (640, 400)] {
+            let mut world = build(width, height);
+            let mut frame = vec![0u8; width * height * 4];
+            let size = format!("{}/{}x{}", name, width, height);
+            // Everything transformed again, as when the camera moves
+            group.bench_function(BenchmarkId::new("full", &size), |b| {
+                b.iter(|| {
+                    world.renderer.clear();
+                    world.draw(black_box(&mut frame));
+                })
+            });
+            // Nothing changed since the last frame
+            group.bench_function(BenchmarkId::new("idle", &size), |b| {
+                b.iter(|| world.draw(black_box(&mut frame)))
+            });
+        };
+    };
+    group.finish();
+}
+
+fn lighting(c: &mut Criterion) {
+    let view = View::new(&Camera::default(), 300, 200, Vec3::new(-0.5, -0.5, 1.0), 1.0);
+    let shape = BoxShape::new(Vec3::ZERO, Vec3::splat(32.0), Color::rgb(0.9, 0.8, 0.6));
+    let lit = shape.get_points().clone();
+    let emissive: Vec<_> = lit.iter().map(|surfel| surfel.with_emissive(true)).collect();
+
+    let mut group = c.benchmark_group("lighting");
+    group.throughput(Throughput::Elements(lit.len() as u64));
+    for (name, points) in [("lit", &lit), ("emissive", &emissive)] {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                for surfel in points.iter() {
+                    black_box(view.shade(surfel));
+                };
+            })
+        });
+    };
+    group.finish();
+}
+
+criterion_group!(benches, draw, lighting);
+criterion_main!(benches);
@@ -0,0 +1,43 @@
+//! Shape construction and the `drawutil` fills shapes are built from.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use glam::Vec3;
+use topdown::{boxshape::BoxShape, color::Color, drawable::Drawable, drawutil};
+
+
+fn box_shape(c: &mut Criterion) {
+    let mut group = c.benchmark_group("box_shape");
+    // Construction grows much faster than the point count, large boxes take a while per run
+    group.sample_size(10);
+    let color = Color::rgb(0.8, 0.4, 0.2);
+    for size in [4.0, 16.0, 32.0, 64.0] {
+        let points = BoxShape::new(Vec3::ZERO, Vec3::splat(size), color).get_points().len();
+        group.throughput(Throughput::Elements(points as u64));
+        group.bench_with_input(BenchmarkId::new("new", size), &size, |b, size| {
+            b.iter(|| BoxShape::new(black_box(Vec3::ZERO), Vec3::splat(*size), color))
+        });
+    };
+    group.finish();
+}
+
+fn fills(c: &mut Criterion) {
+    let mut group = c.benchmark_group("drawutil");
+    let color = Color::rgb(0.8, 0.4, 0.2);
+    for size in [4.0, 16.0, 64.0] {
+        let (start, end) = (Vec3::ZERO, Vec3::new(size, size, size * 0.5));
+        group.throughput(Throughput::Elements(drawutil::fill_vertical(start, end, color).len() as u64));
+        group.bench_with_input(BenchmarkId::new("fill_vertical", size), &end, |b, end| {
+            b.iter(|| drawutil::fill_vertical(black_box(start), *end, color))
+        });
+        group.bench_with_input(BenchmarkId::new("fill_horizontal", size), &end, |b, end| {
+            b.iter(|| drawutil::fill_horizontal(black_box(start), *end, color))
+        });
+        group.bench_with_input(BenchmarkId::new("set_line", size), &end, |b, end| {
+            b.iter(|| drawutil::set_line(black_box(start), *end, color))
+        });
+    };
+    group.finish();
+}
+
+criterion_group!(benches, box_shape, fills);
+criterion_main!(benches);
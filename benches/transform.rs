@@ -0,0 +1,41 @@
+//! Transforming points one at a time against the batched structure-of-arrays path.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use glam::Vec3;
+use topdown::{boxshape::BoxShape, camera::Camera, color::Color, drawable::Drawable, raster::{Positions, View}};
+
+
+fn transform(c: &mut Criterion) {
+    let camera = Camera {
+        target: Vec3::new(10.0, 0.0, -5.0),
+        yaw: 0.6,
+        ..Camera::default()
+    };
+    let view = View::new(&camera, 300, 200, Vec3::new(-0.5, -0.5, 1.0), 1.0);
+
+    let mut group = c.benchmark_group("transform");
+    for size in [8.0, 32.0, 128.0] {
+        let shape = BoxShape::new(Vec3::ZERO, Vec3::splat(size), Color::rgb(1.0, 1.0, 1.0));
+        let points = shape.get_points();
+        let positions = Positions::from_surfels(points);
+        group.throughput(Throughput::Elements(points.len() as u64));
+
+        let mut out = vec![Vec3::ZERO; points.len()];
+        group.bench_function(BenchmarkId::new("scalar", points.len()), |b| {
+            b.iter(|| {
+                for (surfel, out) in points.iter().zip(out.iter_mut()) {
+                    *out = view.to_screen(black_box(surfel.pos));
+                };
+            })
+        });
+
+        let mut screen = Positions::default();
+        group.bench_function(BenchmarkId::new("batched", points.len()), |b| {
+            b.iter(|| black_box(&positions).transform(&view.matrix(Vec3::ZERO), 0..positions.len(), &mut screen))
+        });
+    };
+    group.finish();
+}
+
+criterion_group!(benches, transform);
+criterion_main!(benches);
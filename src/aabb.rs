@@ -0,0 +1,111 @@
+use glam::Vec3;
+
+
+/// Axis-aligned bounding box given by its minimum and maximum corner.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Default for Aabb {
+    fn default() -> Self {
+        Aabb::empty()
+    }
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Aabb {
+            min: min.min(max),
+            max: min.max(max),
+        }
+    }
+
+    pub fn from_center(center: Vec3, half_extents: Vec3) -> Self {
+        Aabb::new(center - half_extents, center + half_extents)
+    }
+
+    /// A box that contains nothing. Growing it by a point gives a box around just that point.
+    pub fn empty() -> Self {
+        Aabb {
+            min: Vec3::splat(f32::MAX),
+            max: Vec3::splat(f32::MIN),
+        }
+    }
+
+    pub fn from_points<'a, I: IntoIterator<Item = &'a Vec3>>(points: I) -> Self {
+        let mut aabb = Aabb::empty();
+        for pt in points {
+            aabb.grow(*pt);
+        };
+        aabb
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn size(&self) -> Vec3 {
+        self.max - self.min
+    }
+
+    pub fn half_extents(&self) -> Vec3 {
+        self.size() * 0.5
+    }
+
+    pub fn grow(&mut self, pt: Vec3) {
+        self.min = self.min.min(pt);
+        self.max = self.max.max(pt);
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    pub fn expanded(&self, amount: Vec3) -> Aabb {
+        Aabb {
+            min: self.min - amount,
+            max: self.max + amount,
+        }
+    }
+
+    pub fn translated(&self, offset: Vec3) -> Aabb {
+        Aabb {
+            min: self.min + offset,
+            max: self.max + offset,
+        }
+    }
+
+    pub fn contains_point(&self, pt: Vec3) -> bool {
+        pt.cmpge(self.min).all() && pt.cmple(self.max).all()
+    }
+
+    pub fn contains(&self, other: &Aabb) -> bool {
+        other.min.cmpge(self.min).all() && other.max.cmple(self.max).all()
+    }
+
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.min.cmple(other.max).all() && self.max.cmpge(other.min).all()
+    }
+
+    pub fn corners(&self) -> [Vec3; 8] {
+        [
+            Vec3::new(self.min.x, self.min.y, self.min.z),
+            Vec3::new(self.max.x, self.min.y, self.min.z),
+            Vec3::new(self.min.x, self.max.y, self.min.z),
+            Vec3::new(self.max.x, self.max.y, self.min.z),
+            Vec3::new(self.min.x, self.min.y, self.max.z),
+            Vec3::new(self.max.x, self.min.y, self.max.z),
+            Vec3::new(self.min.x, self.max.y, self.max.z),
+            Vec3::new(self.max.x, self.max.y, self.max.z),
+        ]
+    }
+}
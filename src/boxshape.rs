@@ -1,6 +1,6 @@
-use glam::{Vec3};
+use glam::{Mat3, Quat, Vec3};
 
-use crate::{Drawable, Color, drawutil::{set_line, fill_vertical, fill_horizontal}};
+use crate::{Drawable, Color, aabb::Aabb, collision, drawable::next_revision, surfel::Surfel, drawutil::{set_line, fill_vertical, fill_horizontal}};
 
 #[derive(Clone, Copy)]
 enum BoxPt {
@@ -27,11 +27,15 @@ pub enum BoxFace {
 
 
 pub struct BoxShape {
-    pub points: Vec<(Vec3, Color, Vec3)>,
-    pub pos: Vec3,
-    pub scale: Vec3,
+    points: Vec<Surfel>,
+    local: Vec<Surfel>,
+    pos: Vec3,
+    scale: Vec3,
+    rotation: Quat,
     
     normals: Vec<Vec3>,
+    bounds: Aabb,
+    revision: u64,
 }
 
 impl BoxShape {
@@ -47,93 +51,179 @@ impl BoxShape {
         normals[BoxFace::Right as usize] = Vec3::new(1.0, 0.0, 0.0);
 
         
-        let mut points = Vec::<(Vec3, Color, Vec3)>::new();
-        points.resize(8, (Vec3::new(0.0, 0.0, 0.0), Color::black(), Vec3::new(0.0, 0.0, 0.0)));
-
-        points[BoxPt::TopFrontL as usize] = (Vec3::new(0.0, 0.0, 0.0), color, normals[BoxFace::Up as usize]);
-        points[BoxPt::TopFrontR as usize] = (Vec3::new(1.0, 0.0, 0.0), color, normals[BoxFace::Up as usize]);
-        points[BoxPt::TopBackL as usize] = (Vec3::new(0.0, 0.0, 1.0), color, normals[BoxFace::Up as usize]);
-        points[BoxPt::TopBackR as usize] = (Vec3::new(1.0, 0.0, 1.0), color, normals[BoxFace::Up as usize]);
-
-        points[BoxPt::BottomFrontL as usize] = (Vec3::new(0.0, 1.0, 0.0), color, normals[BoxFace::Down as usize]);
-        points[BoxPt::BottomFrontR as usize] = (Vec3::new(1.0, 1.0, 0.0), color, normals[BoxFace::Down as usize]);
-        points[BoxPt::BottomBackL as usize] = (Vec3::new(0.0, 1.0, 1.0), color, normals[BoxFace::Down as usize]);
-        points[BoxPt::BottomBackR as usize] = (Vec3::new(1.0, 1.0, 1.0), color, normals[BoxFace::Down as usize]);
+        let mut points = Vec::<Surfel>::new();
+        points.resize(8, Surfel::new(Vec3::new(0.0, 0.0, 0.0), Color::black(), Vec3::new(0.0, 0.0, 0.0)));
+
+        points[BoxPt::TopFrontL as usize] = Surfel::new(Vec3::new(0.0, 0.0, 0.0), color, normals[BoxFace::Up as usize]);
+        points[BoxPt::TopFrontR as usize] = Surfel::new(Vec3::new(1.0, 0.0, 0.0), color, normals[BoxFace::Up as usize]);
+        points[BoxPt::TopBackL as usize] = Surfel::new(Vec3::new(0.0, 0.0, 1.0), color, normals[BoxFace::Up as usize]);
+        points[BoxPt::TopBackR as usize] = Surfel::new(Vec3::new(1.0, 0.0, 1.0), color, normals[BoxFace::Up as usize]);
+
+        points[BoxPt::BottomFrontL as usize] = Surfel::new(Vec3::new(0.0, 1.0, 0.0), color, normals[BoxFace::Down as usize]);
+        points[BoxPt::BottomFrontR as usize] = Surfel::new(Vec3::new(1.0, 1.0, 0.0), color, normals[BoxFace::Down as usize]);
+        points[BoxPt::BottomBackL as usize] = Surfel::new(Vec3::new(0.0, 1.0, 1.0), color, normals[BoxFace::Down as usize]);
+        points[BoxPt::BottomBackR as usize] = Surfel::new(Vec3::new(1.0, 1.0, 1.0), color, normals[BoxFace::Down as usize]);
         for pt in points.iter_mut() {
-            pt.0 -= 0.5;
-            pt.0 *= scale;
-            pt.0 += pos;
+            pt.pos -= 0.5;
+            pt.pos *= scale;
+            pt.pos += pos;
         };
         
         let mut face_points = vec![];
-        for pt in fill_horizontal(points[BoxPt::TopBackR as usize].0, points[BoxPt::TopFrontL as usize].0, color) {
-            if !face_points.contains(&pt.0) {
-                points.push((pt.0, pt.1, normals[BoxFace::Up as usize]));
-                face_points.push(pt.0);
+        for pt in fill_horizontal(points[BoxPt::TopBackR as usize].pos, points[BoxPt::TopFrontL as usize].pos, color) {
+            if !face_points.contains(&pt.pos) {
+                points.push(pt.with_normal(normals[BoxFace::Up as usize]));
+                face_points.push(pt.pos);
             };
         };
-        for pt in fill_vertical(points[BoxPt::TopFrontL as usize].0, points[BoxPt::BottomFrontR as usize].0, color) {
-            if !face_points.contains(&pt.0) {
-                points.push((pt.0, pt.1, normals[BoxFace::Front as usize]));
-                face_points.push(pt.0);
+        for pt in fill_vertical(points[BoxPt::TopFrontL as usize].pos, points[BoxPt::BottomFrontR as usize].pos, color) {
+            if !face_points.contains(&pt.pos) {
+                points.push(pt.with_normal(normals[BoxFace::Front as usize]));
+                face_points.push(pt.pos);
             };
         };
-        for pt in fill_vertical(points[BoxPt::TopBackL as usize].0, points[BoxPt::BottomBackR as usize].0, color) {
-            if !face_points.contains(&pt.0) {
-                points.push((pt.0, pt.1, normals[BoxFace::Back as usize]));
-                face_points.push(pt.0);
+        for pt in fill_vertical(points[BoxPt::TopBackL as usize].pos, points[BoxPt::BottomBackR as usize].pos, color) {
+            if !face_points.contains(&pt.pos) {
+                points.push(pt.with_normal(normals[BoxFace::Back as usize]));
+                face_points.push(pt.pos);
             };
         };
-        for pt in fill_vertical(points[BoxPt::TopFrontL as usize].0, points[BoxPt::BottomBackL as usize].0, color) {
-            if !face_points.contains(&pt.0) {
-                points.push((pt.0, pt.1, normals[BoxFace::Left as usize]));
-                face_points.push(pt.0);
+        for pt in fill_vertical(points[BoxPt::TopFrontL as usize].pos, points[BoxPt::BottomBackL as usize].pos, color) {
+            if !face_points.contains(&pt.pos) {
+                points.push(pt.with_normal(normals[BoxFace::Left as usize]));
+                face_points.push(pt.pos);
             };
         };
-        for pt in fill_vertical(points[BoxPt::TopFrontR as usize].0, points[BoxPt::BottomBackR as usize].0, color) {
-            if !face_points.contains(&pt.0) {
-                points.push((pt.0, pt.1, normals[BoxFace::Right as usize]));
-                face_points.push(pt.0);
+        for pt in fill_vertical(points[BoxPt::TopFrontR as usize].pos, points[BoxPt::BottomBackR as usize].pos, color) {
+            if !face_points.contains(&pt.pos) {
+                points.push(pt.with_normal(normals[BoxFace::Right as usize]));
+                face_points.push(pt.pos);
             };
         };
-        // for pt in fill_horizontal(points[BoxPt::BottomBackR as usize].0, points[BoxPt::BottomFrontL as usize].0, color) {
-        //     if !face_points.contains(&pt.0) {
-        //         points .push((pt.0, pt.1, normals[BoxFace::Down as usize]));
-        //         face_points.push(pt.0);
+        // for pt in fill_horizontal(points[BoxPt::BottomBackR as usize].pos, points[BoxPt::BottomFrontL as usize].pos, color) {
+        //     if !face_points.contains(&pt.pos) {
+        //         points.push(pt.with_normal(normals[BoxFace::Down as usize]));
+        //         face_points.push(pt.pos);
         //     };
         // };
         
 
-        
-        BoxShape {
+        let local = points.iter().map(|pt| Surfel {pos: pt.pos - pos, ..*pt}).collect();
+        let mut shape = BoxShape {
             points,
+            local,
             pos,
             scale,
+            rotation: Quat::IDENTITY,
             
             normals,
+            bounds: Aabb::empty(),
+            revision: 0,
+        };
+        shape.place();
+        shape
+    }
+
+    pub fn pos(&self) -> Vec3 {
+        self.pos
+    }
+
+    pub fn scale(&self) -> Vec3 {
+        self.scale
+    }
+
+    pub fn rotation(&self) -> Quat {
+        self.rotation
+    }
+
+    /// Moves the box and all of its points to `pos`.
+    pub fn set_pos(&mut self, pos: Vec3) {
+        if pos == self.pos {
+            return;
+        };
+        self.pos = pos;
+        self.place();
+    }
+
+    /// Turns the box and all of its points around `pos` to `rotation`.
+    pub fn set_rotation(&mut self, rotation: Quat) {
+        if rotation == self.rotation {
+            return;
+        };
+        self.rotation = rotation.normalize();
+        self.place();
+    }
+
+    /// Axis aligned box around the solid box described by `pos`, `scale` and `rotation`, used for collisions.
+    pub fn collider(&self) -> Aabb {
+        let half = self.scale.abs() * 0.5;
+        let axes = Mat3::from_quat(self.rotation);
+        let extents = axes.x_axis.abs() * half.x + axes.y_axis.abs() * half.y + axes.z_axis.abs() * half.z;
+        Aabb::from_center(self.pos, extents)
+    }
+
+    pub fn overlaps(&self, other: &BoxShape) -> bool {
+        collision::overlaps(&self.collider(), &other.collider())
+    }
+
+    /// Moves the box by `delta`, stopping at and sliding along `obstacles`.
+    /// Returns the normals of the obstacles that were hit.
+    pub fn move_and_slide(&mut self, delta: Vec3, obstacles: &[Aabb]) -> Vec<Vec3> {
+        let slide = collision::move_and_slide(&self.collider(), delta, obstacles);
+        self.set_pos(self.pos + slide.offset);
+        slide.normals
+    }
+
+    /// Moves the box out of `other` along the shortest axis. Returns `false` if they did not overlap.
+    pub fn push_out_of(&mut self, other: &BoxShape) -> bool {
+        match collision::penetration(&self.collider(), &other.collider()) {
+            Some(mtv) => {
+                self.set_pos(self.pos + mtv);
+                true
+            },
+            None => false,
         }
     }
+
+    /// Rebuilds the points from the unrotated ones around the origin, so turning the box
+    /// many times does not add up rounding errors.
+    fn place(&mut self) {
+        for (pt, local) in self.points.iter_mut().zip(self.local.iter()) {
+            pt.pos = self.pos + self.rotation * local.pos;
+            pt.normal = self.rotation * local.normal;
+        };
+        self.bounds = Aabb::from_points(self.points.iter().map(|pt| &pt.pos));
+        self.revision = next_revision();
+    }
 }
 
 
 impl Drawable for BoxShape {
-    fn get_points(&self) -> &Vec<(Vec3, Color, Vec3)> {
+    fn get_points(&self) -> &Vec<Surfel> {
         &self.points
     }
 
+    fn get_bounds(&self) -> Aabb {
+        self.bounds
+    }
+
     fn get_origin(&self) -> Vec3 {
-        if self.points.len() == 0 {
-            return Vec3::new(0.0, 0.0, 0.0);
-        };
-        let mut sum = Vec3::new(0.0, 0.0, 0.0);
-        let mut i = 0;
-        for pt in self.points.iter() {
-            if i == 8 {
-                break;
-            };
-            sum += pt.0;
-            i += 1;
-        };
-        sum / 8.0
+        self.pos
+    }
+
+    fn set_origin(&mut self, origin: Vec3) {
+        self.set_pos(origin);
+    }
+
+    fn set_rotation(&mut self, rotation: Quat) {
+        BoxShape::set_rotation(self, rotation);
+    }
+
+    fn get_collider(&self) -> Option<Aabb> {
+        Some(self.collider())
+    }
+
+    fn revision(&self) -> u64 {
+        self.revision
     }
 }
\ No newline at end of file
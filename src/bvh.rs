@@ -0,0 +1,608 @@
+use glam::Vec3;
+
+use crate::{aabb::Aabb, frustum::Frustum, ray::Ray};
+
+
+const NULL: usize = usize::MAX;
+
+pub type ProxyId = usize;
+
+
+#[derive(Clone)]
+struct Node<T> {
+    /// Enlarged bounds for leaves, union of the children for inner nodes
+    aabb: Aabb,
+    /// Exact bounds as given by the caller, only meaningful for leaves
+    tight: Aabb,
+    parent: usize,
+    left: usize,
+    right: usize,
+    height: i32,
+    data: Option<T>,
+}
+
+impl<T> Node<T> {
+    fn is_leaf(&self) -> bool {
+        self.left == NULL
+    }
+}
+
+
+/// Dynamic bounding volume hierarchy over object bounds.
+///
+/// Leaves store their bounds enlarged by `margin`, so objects that move a little do not
+/// have to be reinserted on every update. Queries test the exact bounds at the leaves.
+#[derive(Clone)]
+pub struct Bvh<T: Copy> {
+    nodes: Vec<Node<T>>,
+    free: Vec<usize>,
+    root: usize,
+    len: usize,
+    pub margin: f32,
+}
+
+impl<T: Copy> Default for Bvh<T> {
+    fn default() -> Self {
+        Bvh::new()
+    }
+}
+
+impl<T: Copy> Bvh<T> {
+    pub fn new() -> Self {
+        Bvh::with_margin(2.0)
+    }
+
+    pub fn with_margin(margin: f32) -> Self {
+        Bvh {
+            nodes: vec![],
+            free: vec![],
+            root: NULL,
+            len: 0,
+            margin,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.free.clear();
+        self.root = NULL;
+        self.len = 0;
+    }
+
+    pub fn data(&self, proxy: ProxyId) -> Option<T> {
+        self.nodes.get(proxy).and_then(|node| node.data)
+    }
+
+    pub fn bounds(&self, proxy: ProxyId) -> Option<Aabb> {
+        self.nodes.get(proxy).filter(|node| node.data.is_some()).map(|node| node.tight)
+    }
+
+    /// Bounds of everything in the tree.
+    pub fn root_bounds(&self) -> Option<Aabb> {
+        match self.root {
+            NULL => None,
+            root => Some(self.nodes[root].aabb),
+        }
+    }
+
+    pub fn insert(&mut self, aabb: Aabb, data: T) -> ProxyId {
+        let leaf = self.alloc_node();
+        let node = &mut self.nodes[leaf];
+        node.aabb = aabb.expanded(Vec3::splat(self.margin));
+        node.tight = aabb;
+        node.height = 0;
+        node.data = Some(data);
+        self.insert_leaf(leaf);
+        self.len += 1;
+        leaf
+    }
+
+    pub fn remove(&mut self, proxy: ProxyId) -> Option<T> {
+        let data = self.nodes.get(proxy).filter(|node| node.is_leaf()).and_then(|node| node.data)?;
+        self.remove_leaf(proxy);
+        self.free_node(proxy);
+        self.len -= 1;
+        Some(data)
+    }
+
+    /// Updates the bounds of a proxy. Returns `true` if it had to be moved within the tree.
+    pub fn update(&mut self, proxy: ProxyId, aabb: Aabb) -> bool {
+        if self.data(proxy).is_none() {
+            return false;
+        };
+        self.nodes[proxy].tight = aabb;
+        if self.nodes[proxy].aabb.contains(&aabb) {
+            return false;
+        };
+        self.remove_leaf(proxy);
+        self.nodes[proxy].aabb = aabb.expanded(Vec3::splat(self.margin));
+        self.insert_leaf(proxy);
+        true
+    }
+
+
+    /// Walks the tree, descending into every node whose bounds pass `test`, and returns the data
+    /// of all leaves whose exact bounds pass it too.
+    pub fn query<F: Fn(&Aabb) -> bool>(&self, test: F) -> Vec<T> {
+        let mut found = vec![];
+        if self.root == NULL {
+            return found;
+        };
+        let mut stack = vec![self.root];
+        while let Some(idx) = stack.pop() {
+            let node = &self.nodes[idx];
+            if !test(&node.aabb) {
+                continue;
+            };
+            if node.is_leaf() {
+                if test(&node.tight) {
+                    found.extend(node.data);
+                };
+            } else {
+                stack.push(node.left);
+                stack.push(node.right);
+            };
+        };
+        found
+    }
+
+    pub fn query_aabb(&self, aabb: &Aabb) -> Vec<T> {
+        self.query(|bounds| bounds.intersects(aabb))
+    }
+
+    pub fn query_point(&self, pt: Vec3) -> Vec<T> {
+        self.query(|bounds| bounds.contains_point(pt))
+    }
+
+    pub fn query_frustum(&self, frustum: &Frustum) -> Vec<T> {
+        self.query(|bounds| frustum.intersects_aabb(bounds))
+    }
+
+    /// Everything whose bounds come within `radius` of `center`.
+    pub fn query_radius(&self, center: Vec3, radius: f32) -> Vec<T> {
+        self.query(|bounds| {
+            let closest = center.clamp(bounds.min, bounds.max);
+            closest.distance_squared(center) <= radius * radius
+        })
+    }
+
+    /// All proxies whose bounds the ray hits within `max_dist`, sorted by entry distance.
+    pub fn raycast_all(&self, ray: &Ray, max_dist: f32) -> Vec<(T, f32)> {
+        let mut hits = vec![];
+        if self.root == NULL {
+            return hits;
+        };
+        let mut stack = vec![self.root];
+        while let Some(idx) = stack.pop() {
+            let node = &self.nodes[idx];
+            match ray.intersect_aabb(&node.aabb) {
+                Some(t) if t <= max_dist => {},
+                _ => continue,
+            };
+            if node.is_leaf() {
+                if let (Some(t), Some(data)) = (ray.intersect_aabb(&node.tight), node.data) {
+                    if t <= max_dist {
+                        hits.push((data, t));
+                    };
+                };
+            } else {
+                stack.push(node.left);
+                stack.push(node.right);
+            };
+        };
+        hits.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+        hits
+    }
+
+    /// Closest hit along the ray. `narrow` refines a bounds hit into an exact distance,
+    /// or rejects it by returning `None`.
+    pub fn raycast<F: FnMut(T, f32) -> Option<f32>>(&self, ray: &Ray, max_dist: f32, mut narrow: F) -> Option<(T, f32)> {
+        let mut best: Option<(T, f32)> = None;
+        for (data, t) in self.raycast_all(ray, max_dist) {
+            if let Some((_, best_t)) = best {
+                // Hits are sorted, nothing further along can beat the current one
+                if t > best_t {
+                    break;
+                };
+            };
+            if let Some(hit_t) = narrow(data, t) {
+                let closer = match best {
+                    Some((_, best_t)) => hit_t < best_t,
+                    None => true,
+                };
+                if hit_t <= max_dist && closer {
+                    best = Some((data, hit_t));
+                };
+            };
+        };
+        best
+    }
+
+
+    fn alloc_node(&mut self) -> usize {
+        let node = Node {
+            aabb: Aabb::empty(),
+            tight: Aabb::empty(),
+            parent: NULL,
+            left: NULL,
+            right: NULL,
+            height: 0,
+            data: None,
+        };
+        match self.free.pop() {
+            Some(idx) => {
+                self.nodes[idx] = node;
+                idx
+            },
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            },
+        }
+    }
+
+    fn free_node(&mut self, idx: usize) {
+        self.nodes[idx].data = None;
+        self.nodes[idx].height = -1;
+        self.free.push(idx);
+    }
+
+    fn insert_leaf(&mut self, leaf: usize) {
+        if self.root == NULL {
+            self.root = leaf;
+            self.nodes[leaf].parent = NULL;
+            return;
+        };
+
+        // Find the cheapest sibling by surface area heuristic
+        let leaf_aabb = self.nodes[leaf].aabb;
+        let mut idx = self.root;
+        while !self.nodes[idx].is_leaf() {
+            let node = &self.nodes[idx];
+            let area = surface_area(&node.aabb);
+            let combined_area = surface_area(&node.aabb.union(&leaf_aabb));
+
+            // Cost of making a new parent for this node and the leaf
+            let cost = 2.0 * combined_area;
+            // Minimum cost of pushing the leaf further down the tree
+            let inheritance = 2.0 * (combined_area - area);
+
+            let child_cost = |child: usize| {
+                let child = &self.nodes[child];
+                let union_area = surface_area(&child.aabb.union(&leaf_aabb));
+                match child.is_leaf() {
+                    true => union_area + inheritance,
+                    false => union_area - surface_area(&child.aabb) + inheritance,
+                }
+            };
+            let cost_left = child_cost(node.left);
+            let cost_right = child_cost(node.right);
+
+            if cost < cost_left && cost < cost_right {
+                break;
+            };
+            idx = match cost_left < cost_right {
+                true => node.left,
+                false => node.right,
+            };
+        };
+
+        let sibling = idx;
+        let old_parent = self.nodes[sibling].parent;
+        let new_parent = self.alloc_node();
+        self.nodes[new_parent].parent = old_parent;
+        self.nodes[new_parent].aabb = leaf_aabb.union(&self.nodes[sibling].aabb);
+        self.nodes[new_parent].height = self.nodes[sibling].height + 1;
+        self.nodes[new_parent].left = sibling;
+        self.nodes[new_parent].right = leaf;
+        self.nodes[sibling].parent = new_parent;
+        self.nodes[leaf].parent = new_parent;
+
+        if old_parent == NULL {
+            self.root = new_parent;
+        } else {
+            self.replace_child(old_parent, sibling, new_parent);
+        };
+
+        self.refit_from(self.nodes[leaf].parent);
+    }
+
+    fn remove_leaf(&mut self, leaf: usize) {
+        if leaf == self.root {
+            self.root = NULL;
+            return;
+        };
+
+        let parent = self.nodes[leaf].parent;
+        let grand_parent = self.nodes[parent].parent;
+        let sibling = match self.nodes[parent].left == leaf {
+            true => self.nodes[parent].right,
+            false => self.nodes[parent].left,
+        };
+
+        if grand_parent == NULL {
+            self.root = sibling;
+            self.nodes[sibling].parent = NULL;
+            self.free_node(parent);
+        } else {
+            self.replace_child(grand_parent, parent, sibling);
+            self.nodes[sibling].parent = grand_parent;
+            self.free_node(parent);
+            self.refit_from(grand_parent);
+        };
+        self.nodes[leaf].parent = NULL;
+    }
+
+    fn replace_child(&mut self, parent: usize, old: usize, new: usize) {
+        if self.nodes[parent].left == old {
+            self.nodes[parent].left = new;
+        } else {
+            self.nodes[parent].right = new;
+        };
+    }
+
+    /// Rebalances and recomputes bounds and heights from `idx` up to the root.
+    fn refit_from(&mut self, mut idx: usize) {
+        while idx != NULL {
+            idx = self.balance(idx);
+            let (left, right) = (self.nodes[idx].left, self.nodes[idx].right);
+            self.nodes[idx].height = 1 + self.nodes[left].height.max(self.nodes[right].height);
+            self.nodes[idx].aabb = self.nodes[left].aabb.union(&self.nodes[right].aabb);
+            idx = self.nodes[idx].parent;
+        };
+    }
+
+    /// Rotates the subtree at `a` if one side is more than one level deeper. Returns the new subtree root.
+    fn balance(&mut self, a: usize) -> usize {
+        if self.nodes[a].is_leaf() || self.nodes[a].height < 2 {
+            return a;
+        };
+
+        let b = self.nodes[a].left;
+        let c = self.nodes[a].right;
+        let balance = self.nodes[c].height - self.nodes[b].height;
+
+        if balance > 1 {
+            self.rotate_up(a, c, b, false)
+        } else if balance < -1 {
+            self.rotate_up(a, b, c, true)
+        } else {
+            a
+        }
+    }
+
+    /// Promotes `child` (the deeper child of `a`) to take `a`'s place. `other` is `a`'s other child.
+    fn rotate_up(&mut self, a: usize, child: usize, other: usize, child_is_left: bool) -> usize {
+        let f = self.nodes[child].left;
+        let g = self.nodes[child].right;
+
+        self.nodes[child].left = a;
+        self.nodes[child].parent = self.nodes[a].parent;
+        self.nodes[a].parent = child;
+
+        let grand_parent = self.nodes[child].parent;
+        if grand_parent == NULL {
+            self.root = child;
+        } else {
+            self.replace_child(grand_parent, a, child);
+        };
+
+        // The taller grandchild stays with `child`, the shorter one moves under `a`
+        let (keep, give) = match self.nodes[f].height > self.nodes[g].height {
+            true => (f, g),
+            false => (g, f),
+        };
+        self.nodes[child].right = keep;
+        match child_is_left {
+            true => self.nodes[a].left = give,
+            false => self.nodes[a].right = give,
+        };
+        self.nodes[give].parent = a;
+
+        self.nodes[a].aabb = self.nodes[other].aabb.union(&self.nodes[give].aabb);
+        self.nodes[a].height = 1 + self.nodes[other].height.max(self.nodes[give].height);
+        self.nodes[child].aabb = self.nodes[a].aabb.union(&self.nodes[keep].aabb);
+        self.nodes[child].height = 1 + self.nodes[a].height.max(self.nodes[keep].height);
+        child
+    }
+}
+
+
+fn surface_area(aabb: &Aabb) -> f32 {
+    let size = aabb.size();
+    2.0 * (size.x * size.y + size.y * size.z + size.z * size.x)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec2;
+
+    use super::*;
+    use crate::{camera::Camera, rng::Rng};
+
+    fn random_aabb(rng: &mut Rng) -> Aabb {
+        let center = Vec3::new(rng.range_f32(-50.0, 50.0), rng.range_f32(-50.0, 50.0), rng.range_f32(-50.0, 50.0));
+        let half = Vec3::new(rng.range_f32(0.1, 5.0), rng.range_f32(0.1, 5.0), rng.range_f32(0.1, 5.0));
+        Aabb::from_center(center, half)
+    }
+
+    fn sorted(mut items: Vec<usize>) -> Vec<usize> {
+        items.sort_unstable();
+        items
+    }
+
+    fn random_point(rng: &mut Rng, extent: f32) -> Vec3 {
+        Vec3::new(rng.range_f32(-extent, extent), rng.range_f32(-extent, extent), rng.range_f32(-extent, extent))
+    }
+
+    /// A tree holding the index of each box in `boxes`.
+    fn filled(rng: &mut Rng, count: usize) -> (Bvh<usize>, Vec<ProxyId>, Vec<Aabb>) {
+        let mut bvh = Bvh::new();
+        let boxes: Vec<Aabb> = (0..count).map(|_| random_aabb(rng)).collect();
+        let proxies = boxes.iter().enumerate().map(|(idx, aabb)| bvh.insert(*aabb, idx)).collect();
+        (bvh, proxies, boxes)
+    }
+
+    fn brute_force<F: Fn(&Aabb) -> bool>(boxes: &[Aabb], test: F) -> Vec<usize> {
+        (0..boxes.len()).filter(|idx| test(&boxes[*idx])).collect()
+    }
+
+    fn check_frustums(rng: &mut Rng, bvh: &Bvh<usize>, boxes: &[Aabb]) {
+        for _ in 0..50 {
+            let camera = Camera {
+                target: random_point(rng, 40.0),
+                yaw: rng.range_f32(0.0, std::f32::consts::TAU),
+                pitch: rng.range_f32(0.2, 1.4),
+                zoom: rng.range_f32(0.5, 4.0),
+            };
+            let frustum = camera.frustum(Vec2::new(150.0, 100.0), Vec2::ZERO, Vec2::new(300.0, 200.0));
+            let expected = brute_force(boxes, |aabb| frustum.intersects_aabb(aabb));
+            assert_eq!(sorted(bvh.query_frustum(&frustum)), expected);
+        };
+    }
+
+    fn check_radius(rng: &mut Rng, bvh: &Bvh<usize>, boxes: &[Aabb]) {
+        for _ in 0..50 {
+            let (center, radius) = (random_point(rng, 60.0), rng.range_f32(0.0, 30.0));
+            let expected = brute_force(boxes, |aabb| {
+                center.clamp(aabb.min, aabb.max).distance_squared(center) <= radius * radius
+            });
+            assert_eq!(sorted(bvh.query_radius(center, radius)), expected);
+        };
+    }
+
+    fn check_rays(rng: &mut Rng, bvh: &Bvh<usize>, boxes: &[Aabb]) {
+        for _ in 0..100 {
+            let ray = Ray::new(random_point(rng, 80.0), random_point(rng, 1.0));
+            let max_dist = rng.range_f32(20.0, 200.0);
+            let mut expected: Vec<(usize, f32)> = (0..boxes.len())
+                .filter_map(|idx| ray.intersect_aabb(&boxes[idx]).filter(|t| *t <= max_dist).map(|t| (idx, t)))
+                .collect();
+            expected.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+            let hits = bvh.raycast_all(&ray, max_dist);
+            let distances: Vec<f32> = hits.iter().map(|(_, t)| *t).collect();
+            assert_eq!(distances, expected.iter().map(|(_, t)| *t).collect::<Vec<_>>());
+            assert_eq!(sorted(hits.iter().map(|(idx, _)| *idx).collect()), sorted(expected.iter().map(|(idx, _)| *idx).collect()));
+
+            let nearest = bvh.raycast(&ray, max_dist, |_, t| Some(t));
+            assert_eq!(nearest.map(|(_, t)| t), expected.first().map(|(_, t)| *t));
+            if let Some((idx, t)) = nearest {
+                assert_eq!(ray.intersect_aabb(&boxes[idx]), Some(t));
+            };
+        };
+    }
+
+    #[test]
+    fn queries_match_brute_force() {
+        let mut rng = Rng::new(3);
+        let mut bvh = Bvh::new();
+        let mut live: Vec<(ProxyId, Aabb, usize)> = vec![];
+        for idx in 0..200 {
+            let aabb = random_aabb(&mut rng);
+            live.push((bvh.insert(aabb, idx), aabb, idx));
+        };
+        // Remove every third one, move some of the rest
+        let mut idx = 0;
+        live.retain(|(proxy, _, _)| {
+            idx += 1;
+            match idx % 3 == 0 {
+                true => {
+                    assert!(bvh.remove(*proxy).is_some());
+                    false
+                },
+                false => true,
+            }
+        });
+        for (proxy, aabb, _) in live.iter_mut().step_by(4) {
+            *aabb = random_aabb(&mut rng);
+            bvh.update(*proxy, *aabb);
+        };
+        assert_eq!(bvh.len(), live.len());
+
+        for _ in 0..50 {
+            let query = random_aabb(&mut rng).expanded(Vec3::splat(10.0));
+            let expected = live.iter().filter(|(_, aabb, _)| aabb.intersects(&query)).map(|(_, _, idx)| *idx).collect();
+            assert_eq!(sorted(bvh.query_aabb(&query)), sorted(expected));
+
+            let pt = query.center();
+            let expected = live.iter().filter(|(_, aabb, _)| aabb.contains_point(pt)).map(|(_, _, idx)| *idx).collect();
+            assert_eq!(sorted(bvh.query_point(pt)), sorted(expected));
+        };
+    }
+
+    #[test]
+    fn removed_proxies_are_gone() {
+        let mut bvh = Bvh::new();
+        let aabb = Aabb::from_center(Vec3::ZERO, Vec3::ONE);
+        let proxy = bvh.insert(aabb, 7u32);
+        assert_eq!(bvh.remove(proxy), Some(7));
+        assert_eq!(bvh.remove(proxy), None);
+        assert!(bvh.is_empty());
+        assert!(bvh.query_aabb(&aabb).is_empty());
+        assert_eq!(bvh.root_bounds(), None);
+    }
+
+    #[test]
+    fn frustum_queries_match_brute_force() {
+        let mut rng = Rng::new(17);
+        let (bvh, _, boxes) = filled(&mut rng, 300);
+        check_frustums(&mut rng, &bvh, &boxes);
+    }
+
+    #[test]
+    fn radius_queries_match_brute_force() {
+        let mut rng = Rng::new(19);
+        let (bvh, _, boxes) = filled(&mut rng, 300);
+        check_radius(&mut rng, &bvh, &boxes);
+    }
+
+    #[test]
+    fn raycasts_match_brute_force() {
+        let mut rng = Rng::new(23);
+        let (bvh, _, boxes) = filled(&mut rng, 300);
+        check_rays(&mut rng, &bvh, &boxes);
+        // A narrow phase that rejects the nearest hit falls through to the next one
+        let mut bvh = Bvh::new();
+        bvh.insert(Aabb::from_center(Vec3::new(10.0, 0.0, 0.0), Vec3::ONE), 0);
+        bvh.insert(Aabb::from_center(Vec3::new(20.0, 0.0, 0.0), Vec3::ONE), 1);
+        let ray = Ray::new(Vec3::ZERO, Vec3::X);
+        assert_eq!(bvh.raycast(&ray, f32::MAX, |_, t| Some(t)), Some((0, 9.0)));
+        assert_eq!(bvh.raycast(&ray, f32::MAX, |idx, t| (idx != 0).then_some(t)), Some((1, 19.0)));
+        assert_eq!(bvh.raycast(&ray, 15.0, |idx, t| (idx != 0).then_some(t)), None);
+    }
+
+    #[test]
+    fn queries_follow_updates() {
+        let mut rng = Rng::new(29);
+        let (mut bvh, proxies, mut boxes) = filled(&mut rng, 300);
+        for round in 0..3 {
+            for (proxy, aabb) in proxies.iter().zip(boxes.iter_mut()) {
+                // Small moves stay inside the enlarged bounds, large ones move the leaf
+                *aabb = match round {
+                    0 => aabb.translated(random_point(&mut rng, 0.5)),
+                    _ => random_aabb(&mut rng),
+                };
+                bvh.update(*proxy, *aabb);
+                assert_eq!(bvh.bounds(*proxy), Some(*aabb));
+            };
+            for _ in 0..20 {
+                let query = random_aabb(&mut rng).expanded(Vec3::splat(10.0));
+                assert_eq!(sorted(bvh.query_aabb(&query)), brute_force(&boxes, |aabb| aabb.intersects(&query)));
+            };
+            check_frustums(&mut rng, &bvh, &boxes);
+            check_radius(&mut rng, &bvh, &boxes);
+            check_rays(&mut rng, &bvh, &boxes);
+        };
+        assert!(bvh.root_bounds().unwrap().contains(&boxes.iter().fold(Aabb::empty(), |all, aabb| all.union(aabb))));
+    }
+}
@@ -0,0 +1,161 @@
+use glam::{Vec2, Vec3, Mat3};
+
+use crate::{aabb::Aabb, frustum::Frustum, input::Actions, picking, ray::Ray};
+
+
+/// Orthographic camera looking at `target`, which is drawn at the center of the screen.
+///
+/// A world point `p` ends up at `rotation * (p - target) * zoom + center` on screen,
+/// where a larger z means closer to the viewer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Camera {
+    pub target: Vec3,
+    /// Rotation around the vertical axis in radians
+    pub yaw: f32,
+    /// Tilt towards looking straight down in radians
+    pub pitch: f32,
+    /// Screen pixels per world unit
+    pub zoom: f32,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Camera {
+            target: Vec3::ZERO,
+            yaw: 0.0,
+            pitch: 0.75,
+            zoom: 1.0,
+        }
+    }
+}
+
+impl Camera {
+    pub fn rotation(&self) -> Mat3 {
+        let rot_x = Mat3::from_cols(
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, self.pitch.cos(), -self.pitch.sin()),
+            Vec3::new(0.0, self.pitch.sin(), self.pitch.cos()),
+        );
+        let rot_y = Mat3::from_cols(
+            Vec3::new(self.yaw.cos(), 0.0, self.yaw.sin()),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(-self.yaw.sin(), 0.0, self.yaw.cos()),
+        );
+        rot_x * rot_y
+    }
+
+    pub fn to_screen(&self, pt: Vec3, center: Vec2) -> Vec3 {
+        self.rotation() * (pt - self.target) * self.zoom + center.extend(0.0)
+    }
+
+    /// World space volume that lands within `min..=max` on a screen centered at `center`.
+    pub fn frustum(&self, center: Vec2, min: Vec2, max: Vec2) -> Frustum {
+        let rotation = self.rotation();
+        Frustum::orthographic(
+            rotation,
+            rotation * self.target,
+            (min - center) / self.zoom,
+            (max - center) / self.zoom,
+        )
+    }
+
+    /// Ray into the scene through a screen position, starting in front of `scene_bounds`.
+    pub fn screen_ray(&self, screen: Vec2, center: Vec2, scene_bounds: &Aabb) -> Ray {
+        let rotation = self.rotation();
+        picking::ortho_screen_ray(rotation, rotation * self.target, (screen - center) / self.zoom, scene_bounds)
+    }
+
+    /// Screen space direction `dir` turned into a world space direction.
+    pub fn screen_to_world_dir(&self, dir: Vec2) -> Vec3 {
+        self.rotation().transpose() * dir.extend(0.0) / self.zoom
+    }
+
+    /// Direction on the ground plane that appears to move along `screen_dir` on screen.
+    pub fn ground_dir(&self, screen_dir: Vec2) -> Vec3 {
+        // Only the yaw matters, as long as the camera looks down onto the ground
+        let inv_yaw = Camera {pitch: 0.0, ..*self}.rotation().transpose();
+        let right = inv_yaw * Vec3::X;
+        let down = inv_yaw * Vec3::Z;
+        (right * screen_dir.x + down * screen_dir.y).normalize_or_zero()
+    }
+
+    pub fn lerp(&self, other: &Camera, t: f32) -> Camera {
+        Camera {
+            target: self.target.lerp(other.target, t),
+            yaw: self.yaw + (other.yaw - self.yaw) * t,
+            pitch: self.pitch + (other.pitch - self.pitch) * t,
+            zoom: self.zoom + (other.zoom - self.zoom) * t,
+        }
+    }
+}
+
+
+/// Orbits, pans and zooms a `Camera` from input actions.
+///
+/// Uses the `orbit` and `pan` actions while dragging with the `look_x`/`look_y` axes,
+/// `pan_x`/`pan_y` for panning along the ground, `zoom` for zooming and
+/// `toggle_auto_rotate` to switch the idle spin on and off.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraController {
+    /// Radians per pixel of mouse movement
+    pub orbit_speed: f32,
+    /// World units per second when panning with keys, at zoom 1
+    pub pan_speed: f32,
+    /// Zoom factor per wheel step
+    pub zoom_step: f32,
+    pub min_zoom: f32,
+    pub max_zoom: f32,
+    pub min_pitch: f32,
+    pub max_pitch: f32,
+    pub auto_rotate: bool,
+    /// Radians per second
+    pub auto_rotate_speed: f32,
+}
+
+impl Default for CameraController {
+    fn default() -> Self {
+        CameraController {
+            orbit_speed: 0.005,
+            pan_speed: 120.0,
+            zoom_step: 1.1,
+            min_zoom: 0.1,
+            max_zoom: 10.0,
+            min_pitch: 0.0,
+            max_pitch: std::f32::consts::FRAC_PI_2,
+            auto_rotate: true,
+            auto_rotate_speed: 1.0,
+        }
+    }
+}
+
+impl CameraController {
+    pub fn update(&mut self, camera: &mut Camera, actions: &Actions, dt: f32) {
+        if actions.pressed("toggle_auto_rotate") {
+            self.auto_rotate = !self.auto_rotate;
+        };
+        let look = Vec2::new(actions.axis("look_x"), actions.axis("look_y"));
+
+        if actions.held("orbit") {
+            camera.yaw += look.x * self.orbit_speed;
+            camera.pitch = (camera.pitch - look.y * self.orbit_speed).clamp(self.min_pitch, self.max_pitch);
+        } else if self.auto_rotate {
+            camera.yaw += self.auto_rotate_speed * dt;
+        };
+
+        if actions.held("pan") {
+            // Drag the scene along with the cursor
+            camera.target -= camera.screen_to_world_dir(look);
+        };
+
+        let movement = Vec2::new(actions.axis("pan_x"), actions.axis("pan_y"));
+        if movement != Vec2::ZERO {
+            camera.target += camera.ground_dir(movement) * self.pan_speed * dt / camera.zoom;
+        };
+
+        let zoom = actions.axis("zoom");
+        if zoom != 0.0 {
+            camera.zoom = (camera.zoom * self.zoom_step.powf(zoom)).clamp(self.min_zoom, self.max_zoom);
+        };
+    }
+
+}
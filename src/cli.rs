@@ -0,0 +1,160 @@
+use std::{fmt, path::PathBuf};
+
+
+pub const USAGE: &str = "\
+Usage: topdown [options]
+
+Options:
+  --width <pixels>    Internal render width (default 300)
+  --height <pixels>   Internal render height (default 200)
+  --scale <factor>    Window size as a multiple of the render size (default 3)
+  --fit               Make the resolution follow the window size instead of letterboxing
+  --scene <file>      Scene to load (default scenes/default.json)
+  --tick-rate <hz>    Simulation ticks per second (default 25)
+  --headless          Render without opening a window
+  --frames <count>    Number of frames to render in headless mode (default 1)
+  --out <dir>         Where headless mode writes its frames (default frames)
+  -h, --help          Print this help";
+
+
+#[derive(Debug, PartialEq)]
+pub enum CliError {
+    /// `--help` was given, the caller should print `USAGE` and stop
+    Help,
+    UnknownFlag(String),
+    MissingValue(String),
+    InvalidValue {flag: String, value: String},
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Help => write!(f, "help requested"),
+            CliError::UnknownFlag(flag) => write!(f, "unknown option '{}'", flag),
+            CliError::MissingValue(flag) => write!(f, "'{}' needs a value", flag),
+            CliError::InvalidValue {flag, value} => write!(f, "invalid value '{}' for '{}'", value, flag),
+        }
+    }
+}
+
+impl std::error::Error for CliError {}
+
+
+/// Settings for a run of the binary, taken from the command line.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Options {
+    pub width: u32,
+    pub height: u32,
+    pub scale: f64,
+    /// Let the resolution follow the window rather than keeping `width` and `height`
+    pub fit: bool,
+    /// `None` for the default scene, which falls back to the built-in copy when missing
+    pub scene: Option<PathBuf>,
+    /// Simulation ticks per second
+    pub tick_rate: f32,
+    pub headless: bool,
+    pub frames: u32,
+    pub out: PathBuf,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            width: 300,
+            height: 200,
+            scale: 3.0,
+            fit: false,
+            scene: None,
+            tick_rate: 25.0,
+            headless: false,
+            frames: 1,
+            out: PathBuf::from("frames"),
+        }
+    }
+}
+
+impl Options {
+    /// Reads the options from the process arguments.
+    pub fn from_env() -> Result<Self, CliError> {
+        Options::parse(std::env::args().skip(1))
+    }
+
+    /// Parses arguments, not including the program name. Values may be given as `--flag value`
+    /// or `--flag=value`.
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, CliError> {
+        let mut options = Options::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (flag, inline) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+                _ => (arg, None),
+            };
+            let mut value = || inline.clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| CliError::MissingValue(flag.clone()));
+            match flag.as_str() {
+                "-h" | "--help" => return Err(CliError::Help),
+                "--width" => options.width = parse_positive(&flag, value()?)?,
+                "--height" => options.height = parse_positive(&flag, value()?)?,
+                "--scale" => options.scale = parse_positive(&flag, value()?)?,
+                "--fit" => options.fit = true,
+                "--scene" => options.scene = Some(PathBuf::from(value()?)),
+                "--tick-rate" => options.tick_rate = parse_positive(&flag, value()?)?,
+                "--headless" => options.headless = true,
+                "--frames" => options.frames = parse_value(&flag, value()?)?,
+                "--out" => options.out = PathBuf::from(value()?),
+                _ => return Err(CliError::UnknownFlag(flag)),
+            };
+        };
+        Ok(options)
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(flag: &str, value: String) -> Result<T, CliError> {
+    value.parse().map_err(|_| CliError::InvalidValue {flag: flag.to_string(), value})
+}
+
+fn parse_positive<T: std::str::FromStr + PartialOrd + Default>(flag: &str, value: String) -> Result<T, CliError> {
+    let parsed: T = parse_value(flag, value.clone())?;
+    match parsed > T::default() {
+        true => Ok(parsed),
+        false => Err(CliError::InvalidValue {flag: flag.to_string(), value}),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, CliError> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn defaults() {
+        assert_eq!(parse(&[]), Ok(Options::default()));
+    }
+
+    #[test]
+    fn both_value_forms() {
+        let options = parse(&["--width", "640", "--height=400", "--scene=a=b.json", "--tick-rate=60", "--fit", "--headless"]).unwrap();
+        assert_eq!((options.width, options.height), (640, 400));
+        // Only the first `=` splits, the rest belongs to the value
+        assert_eq!(options.scene, Some(PathBuf::from("a=b.json")));
+        assert_eq!(options.tick_rate, 60.0);
+        assert!(options.fit && options.headless);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse(&["--width"]), Err(CliError::MissingValue("--width".to_string())));
+        assert_eq!(parse(&["--width", "0"]), Err(CliError::InvalidValue {flag: "--width".to_string(), value: "0".to_string()}));
+        assert_eq!(parse(&["--width=-3"]), Err(CliError::InvalidValue {flag: "--width".to_string(), value: "-3".to_string()}));
+        assert_eq!(parse(&["--scale", "0"]), Err(CliError::InvalidValue {flag: "--scale".to_string(), value: "0".to_string()}));
+        assert_eq!(parse(&["--tick-rate=-1"]), Err(CliError::InvalidValue {flag: "--tick-rate".to_string(), value: "-1".to_string()}));
+        assert_eq!(parse(&["--frames", "lots"]), Err(CliError::InvalidValue {flag: "--frames".to_string(), value: "lots".to_string()}));
+        assert_eq!(parse(&["--wdith", "3"]), Err(CliError::UnknownFlag("--wdith".to_string())));
+        assert_eq!(parse(&["--width", "3", "-h"]), Err(CliError::Help));
+    }
+}
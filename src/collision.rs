@@ -0,0 +1,195 @@
+use glam::Vec3;
+
+use crate::aabb::Aabb;
+
+
+/// Velocities smaller than this on an axis are treated as not moving along it.
+const EPSILON: f32 = 1e-6;
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SweepHit {
+    /// Fraction of the movement, `0..=1`, after which the boxes touch
+    pub time: f32,
+    /// Surface normal of the box that was hit, pointing towards the moving box
+    pub normal: Vec3,
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SlideResult {
+    /// How far the box actually moved
+    pub offset: Vec3,
+    /// Normals of everything that was hit along the way
+    pub normals: Vec<Vec3>,
+}
+
+
+/// Boxes that only touch are not overlapping.
+pub fn overlaps(a: &Aabb, b: &Aabb) -> bool {
+    a.min.cmplt(b.max).all() && a.max.cmpgt(b.min).all()
+}
+
+/// Smallest translation that moves `a` out of `b`, if they overlap.
+pub fn penetration(a: &Aabb, b: &Aabb) -> Option<Vec3> {
+    let depth = a.max.min(b.max) - a.min.max(b.min);
+    if depth.cmple(Vec3::ZERO).any() {
+        return None;
+    };
+    let dir = a.center() - b.center();
+    let axis = match (depth.x <= depth.y, depth.x <= depth.z, depth.y <= depth.z) {
+        (true, true, _) => Vec3::X,
+        (false, _, true) => Vec3::Y,
+        _ => Vec3::Z,
+    };
+    let sign = match axis.dot(dir) < 0.0 {
+        true => -1.0,
+        false => 1.0,
+    };
+    Some(axis * depth * sign)
+}
+
+/// When moving `moving` by `velocity`, the earliest time it touches `other`.
+/// Boxes that already overlap or only slide along each other do not count as a hit.
+pub fn sweep(moving: &Aabb, velocity: Vec3, other: &Aabb) -> Option<SweepHit> {
+    // Shrink the moving box to a point and grow the other box by the same amount
+    let expanded = other.expanded(moving.half_extents());
+    let origin = moving.center();
+
+    let mut t_near = f32::MIN;
+    let mut t_far = f32::MAX;
+    let mut normal = Vec3::ZERO;
+    for axis in 0..3 {
+        let (o, v) = (origin[axis], velocity[axis]);
+        let (lo, hi) = (expanded.min[axis], expanded.max[axis]);
+        if v.abs() < EPSILON {
+            if o <= lo || o >= hi {
+                return None;
+            };
+            continue;
+        };
+        let t1 = (lo - o) / v;
+        let t2 = (hi - o) / v;
+        let (t_enter, t_exit) = (t1.min(t2), t1.max(t2));
+        if t_enter > t_near {
+            t_near = t_enter;
+            normal = Vec3::ZERO;
+            normal[axis] = -v.signum();
+        };
+        t_far = t_far.min(t_exit);
+        if t_near >= t_far {
+            return None;
+        };
+    };
+
+    match (0.0..=1.0).contains(&t_near) {
+        true => Some(SweepHit {time: t_near, normal}),
+        false => None,
+    }
+}
+
+/// Moves `aabb` by `velocity`, stopping at obstacles and sliding along their surfaces.
+pub fn move_and_slide(aabb: &Aabb, mut velocity: Vec3, obstacles: &[Aabb]) -> SlideResult {
+    let mut current = *aabb;
+    let mut result = SlideResult {
+        offset: Vec3::ZERO,
+        normals: vec![],
+    };
+    // A box wedged into a corner needs one pass per axis at most
+    for _ in 0..3 {
+        if velocity.length_squared() < EPSILON {
+            break;
+        };
+        let earliest = obstacles.iter()
+            .filter_map(|obstacle| sweep(&current, velocity, obstacle))
+            .min_by(|a, b| a.time.partial_cmp(&b.time).unwrap_or(std::cmp::Ordering::Equal));
+
+        match earliest {
+            Some(hit) => {
+                let step = velocity * hit.time;
+                current = current.translated(step);
+                result.offset += step;
+                // Keep only the part of the remaining movement along the surface
+                let remaining = velocity * (1.0 - hit.time);
+                velocity = remaining - hit.normal * remaining.dot(hit.normal);
+                result.normals.push(hit.normal);
+            },
+            None => {
+                result.offset += velocity;
+                break;
+            },
+        };
+    };
+    result
+}
+
+/// Pushes `aabb` out of every obstacle it overlaps. Returns the total translation.
+pub fn resolve_overlaps(aabb: &Aabb, obstacles: &[Aabb]) -> Vec3 {
+    let mut current = *aabb;
+    let mut offset = Vec3::ZERO;
+    for obstacle in obstacles {
+        if let Some(mtv) = penetration(&current, obstacle) {
+            current = current.translated(mtv);
+            offset += mtv;
+        };
+    };
+    offset
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit_box(center: Vec3) -> Aabb {
+        Aabb::from_center(center, Vec3::splat(0.5))
+    }
+
+    #[test]
+    fn sweep_time_of_impact() {
+        // A gap of 2 between the faces, covered at a fifth of a movement of 10
+        let hit = sweep(&unit_box(Vec3::ZERO), Vec3::new(10.0, 0.0, 0.0), &unit_box(Vec3::new(3.0, 0.0, 0.0))).unwrap();
+        assert!((hit.time - 0.2).abs() < 1e-5);
+        assert_eq!(hit.normal, Vec3::new(-1.0, 0.0, 0.0));
+
+        let hit = sweep(&unit_box(Vec3::ZERO), Vec3::new(0.0, -4.0, 0.0), &unit_box(Vec3::new(0.0, -2.0, 0.0))).unwrap();
+        assert!((hit.time - 0.25).abs() < 1e-5);
+        assert_eq!(hit.normal, Vec3::Y);
+    }
+
+    #[test]
+    fn sweep_misses() {
+        let other = unit_box(Vec3::new(3.0, 0.0, 0.0));
+        // Too short, going the other way, and passing beside it
+        assert_eq!(sweep(&unit_box(Vec3::ZERO), Vec3::new(1.0, 0.0, 0.0), &other), None);
+        assert_eq!(sweep(&unit_box(Vec3::ZERO), Vec3::new(-10.0, 0.0, 0.0), &other), None);
+        assert_eq!(sweep(&unit_box(Vec3::new(0.0, 2.0, 0.0)), Vec3::new(10.0, 0.0, 0.0), &other), None);
+        // Sliding along a face is not a hit
+        assert_eq!(sweep(&unit_box(Vec3::new(0.0, 1.0, 0.0)), Vec3::new(10.0, 0.0, 0.0), &other), None);
+    }
+
+    #[test]
+    fn penetration_takes_shallowest_axis() {
+        let b = Aabb::new(Vec3::ZERO, Vec3::splat(2.0));
+        // Overlapping by 0.25 in x and 1.5 in y and z
+        let a = Aabb::new(Vec3::new(1.75, 0.5, 0.5), Vec3::new(2.75, 3.5, 3.5));
+        assert_eq!(penetration(&a, &b), Some(Vec3::new(0.25, 0.0, 0.0)));
+        // Mirrored, it gets pushed the other way
+        let a = Aabb::new(Vec3::new(-0.75, 0.5, 0.5), Vec3::new(0.25, 3.5, 3.5));
+        assert_eq!(penetration(&a, &b), Some(Vec3::new(-0.25, 0.0, 0.0)));
+        let a = Aabb::new(Vec3::new(0.5, 0.5, -1.0), Vec3::new(1.5, 1.5, 0.5));
+        assert_eq!(penetration(&a, &b), Some(Vec3::new(0.0, 0.0, -0.5)));
+        // Touching is not overlapping
+        let a = Aabb::new(Vec3::new(2.0, 0.0, 0.0), Vec3::new(3.0, 1.0, 1.0));
+        assert_eq!(penetration(&a, &b), None);
+        assert!(!overlaps(&a, &b));
+    }
+
+    #[test]
+    fn slides_along_wall() {
+        let wall = Aabb::new(Vec3::new(1.0, -10.0, -10.0), Vec3::new(2.0, 10.0, 10.0));
+        let result = move_and_slide(&unit_box(Vec3::ZERO), Vec3::new(2.0, 3.0, 0.0), &[wall]);
+        assert!((result.offset - Vec3::new(0.5, 3.0, 0.0)).length() < 1e-5);
+        assert_eq!(result.normals, vec![Vec3::new(-1.0, 0.0, 0.0)]);
+    }
+}
@@ -2,7 +2,7 @@ use std::ops::{Sub, Mul, Add, Div, MulAssign, DivAssign, AddAssign, SubAssign};
 
 
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Color {
     pub r: f32,
     pub g: f32,
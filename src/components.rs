@@ -0,0 +1,167 @@
+use glam::{Affine3A, Quat, Vec3};
+
+use crate::{drawable::Drawable, ecs::{Ecs, Entity}, scene::{self, GlobalTransform, Parent}};
+
+
+/// Where an entity is, relative to its `Parent` if it has one and in world space otherwise.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Transform {
+    pub position: Vec3,
+    pub rotation: Quat,
+}
+
+impl Transform {
+    pub fn new(position: Vec3) -> Self {
+        Transform {
+            position,
+            rotation: Quat::IDENTITY,
+        }
+    }
+
+    pub fn with_rotation(self, rotation: Quat) -> Self {
+        Transform {rotation, ..self}
+    }
+
+    pub fn matrix(&self) -> Affine3A {
+        Affine3A::from_rotation_translation(self.rotation, self.position)
+    }
+}
+
+
+/// World units per second, in the parent's space for attached entities.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Velocity(pub Vec3);
+
+
+/// Geometry drawn for an entity, as an index into `World::objects`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Renderable {
+    pub object: usize,
+}
+
+
+pub type ScriptFn = dyn FnMut(Entity, &mut Ecs, f32);
+
+/// Behaviour attached to a single entity, called once per tick.
+pub struct Script(pub Box<ScriptFn>);
+
+impl Script {
+    pub fn new<F: FnMut(Entity, &mut Ecs, f32) + 'static>(f: F) -> Self {
+        Script(Box::new(f))
+    }
+}
+
+
+pub fn run_scripts(ecs: &mut Ecs, dt: f32) {
+    for entity in ecs.with::<Script>() {
+        // Taken out while running, so the script can change its own entity
+        if let Some(mut script) = ecs.remove::<Script>(entity) {
+            (script.0)(entity, ecs, dt);
+            if ecs.is_alive(entity) && !ecs.has::<Script>(entity) {
+                ecs.insert(entity, script);
+            };
+        };
+    };
+}
+
+/// Moves entities by their velocity. Nothing stops them, collisions are left to `Physics`, which
+/// moves the objects that have a `RigidBody`.
+pub fn integrate_velocities(ecs: &mut Ecs, dt: f32) {
+    for entity in ecs.with::<Velocity>() {
+        let delta = match ecs.get::<Velocity>(entity) {
+            Some(velocity) => velocity.0 * dt,
+            None => continue,
+        };
+        if let Some(transform) = ecs.get_mut::<Transform>(entity) {
+            transform.position += delta;
+        };
+    };
+}
+
+/// Moves and turns drawables to where their entity is in the world.
+pub fn push_transforms(ecs: &Ecs, objects: &mut [Box<dyn Drawable>]) {
+    let renderables = match ecs.storage::<Renderable>() {
+        Some(renderables) => renderables,
+        None => return,
+    };
+    for (entity, renderable) in renderables.iter() {
+        let object = match objects.get_mut(renderable.object) {
+            Some(object) => object,
+            None => continue,
+        };
+        let (position, rotation) = match (ecs.get::<GlobalTransform>(entity), ecs.get::<Transform>(entity)) {
+            (Some(global), _) => (global.position(), global.rotation()),
+            (None, Some(transform)) => (transform.position, transform.rotation),
+            (None, None) => continue,
+        };
+        object.set_rotation(rotation);
+        if object.get_origin() != position {
+            object.set_origin(position);
+        };
+    };
+}
+
+/// Picks up drawables that were moved directly, e.g. by physics, into their entity's transform.
+/// Attached entities get the new place relative to their parent, so they keep following it.
+pub fn pull_transforms(ecs: &mut Ecs, objects: &[Box<dyn Drawable>]) {
+    for entity in ecs.with::<Renderable>() {
+        let object = match ecs.get::<Renderable>(entity).and_then(|renderable| objects.get(renderable.object)) {
+            Some(object) => object,
+            None => continue,
+        };
+        let origin = object.get_origin();
+        let position = match ecs.get::<Parent>(entity) {
+            Some(Parent(parent)) => {
+                // Untouched children would pick up rounding errors from the round trip
+                if ecs.get::<GlobalTransform>(entity).map(|global| global.position()) == Some(origin) {
+                    continue;
+                };
+                let parent_matrix = match ecs.get::<GlobalTransform>(*parent) {
+                    Some(global) => global.0,
+                    None => scene::world_matrix(ecs, *parent),
+                };
+                parent_matrix.inverse().transform_point3(origin)
+            },
+            None => origin,
+        };
+        if let Some(transform) = ecs.get_mut::<Transform>(entity) {
+            transform.position = position;
+        };
+    };
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{boxshape::BoxShape, color::Color};
+
+    #[test]
+    fn pulls_attached_bodies_into_parent_space() {
+        let mut ecs = Ecs::new();
+        let mut objects: Vec<Box<dyn Drawable>> = vec![
+            Box::new(BoxShape::new(Vec3::ZERO, Vec3::splat(2.0), Color::rgb(1.0, 0.0, 0.0))),
+            Box::new(BoxShape::new(Vec3::ZERO, Vec3::splat(2.0), Color::rgb(0.0, 1.0, 0.0))),
+        ];
+        let parent = ecs.spawn();
+        ecs.insert(parent, Transform {position: Vec3::new(10.0, 0.0, 0.0), rotation: Quat::from_rotation_y(1.0)});
+        let moved = ecs.spawn();
+        let still = ecs.spawn();
+        for (object, child) in [moved, still].into_iter().enumerate() {
+            ecs.insert(child, Transform {position: Vec3::new(3.0, 0.0, 1.0), rotation: Quat::IDENTITY});
+            ecs.insert(child, Renderable {object});
+            scene::set_parent(&mut ecs, child, Some(parent));
+        };
+        let still_local = *ecs.get::<Transform>(still).unwrap();
+
+        scene::propagate_transforms(&mut ecs);
+        push_transforms(&ecs, &mut objects);
+        let target = objects[0].get_origin() + Vec3::new(0.0, 0.0, 2.0);
+        objects[0].set_origin(target);
+        pull_transforms(&mut ecs, &objects);
+        scene::propagate_transforms(&mut ecs);
+
+        assert!(ecs.get::<GlobalTransform>(moved).unwrap().position().abs_diff_eq(target, 1e-5));
+        assert_eq!(*ecs.get::<Transform>(still).unwrap(), still_local);
+    }
+}
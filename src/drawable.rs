@@ -1,7 +1,46 @@
-use glam::{Vec3};
-use crate::color::Color;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use glam::{Quat, Vec3};
+use crate::{aabb::Aabb, surfel::Surfel};
+
+
+static NEXT_REVISION: AtomicU64 = AtomicU64::new(1);
+
+/// A revision no object has had before. Taking one on creation and on every change means an
+/// object replaced by another one at the same index never looks unchanged.
+pub fn next_revision() -> u64 {
+    NEXT_REVISION.fetch_add(1, Ordering::Relaxed)
+}
+
 
 pub trait Drawable {
-    fn get_points(&self) -> &Vec<(Vec3, Color, Vec3)>;
-    fn get_origin(&self) -> Vec3;
+    fn get_points(&self) -> &Vec<Surfel>;
+
+    fn get_bounds(&self) -> Aabb {
+        Aabb::from_points(self.get_points().iter().map(|pt| &pt.pos))
+    }
+
+    fn get_origin(&self) -> Vec3 {
+        self.get_bounds().center()
+    }
+
+    /// Moves the object so that `get_origin` returns `origin`. Objects that cannot move ignore this.
+    fn set_origin(&mut self, _origin: Vec3) {}
+
+    /// Turns the object around its origin to `rotation`. Objects that cannot turn ignore this.
+    fn set_rotation(&mut self, _rotation: Quat) {}
+
+    /// Solid volume other objects collide with, `None` for objects without collision.
+    fn get_collider(&self) -> Option<Aabb> {
+        None
+    }
+
+    /// Called once per `World::update` with the elapsed simulation time in seconds.
+    fn update(&mut self, _dt: f32) {}
+
+    /// Changes every time the points of this object change, to a value from `next_revision`, so
+    /// renderers can cache per-object work. `0` means changes aren't tracked and nothing is cached.
+    fn revision(&self) -> u64 {
+        0
+    }
 }
\ No newline at end of file
@@ -1,10 +1,10 @@
 use glam::Vec3;
 
-use crate::Color;
+use crate::{Color, surfel::Surfel};
 
 
 
-pub fn fill_vertical(start: Vec3, end: Vec3, color: Color) -> Vec<(Vec3, Color)> {
+pub fn fill_vertical(start: Vec3, end: Vec3, color: Color) -> Vec<Surfel> {
     let mut points = vec![];
     let (min, max) = match start.y > end.y {
         true => (end.y.round() as i32, start.y.round() as i32),
@@ -24,7 +24,7 @@ pub fn fill_vertical(start: Vec3, end: Vec3, color: Color) -> Vec<(Vec3, Color)>
 }
 
 
-pub fn fill_horizontal(start: Vec3, end: Vec3, color: Color) -> Vec<(Vec3, Color)> {
+pub fn fill_horizontal(start: Vec3, end: Vec3, color: Color) -> Vec<Surfel> {
     let mut points = vec![];
     let (min, max) = match start.x > end.x {
         true => (end.x.round() as i32, start.x.round() as i32),
@@ -44,7 +44,7 @@ pub fn fill_horizontal(start: Vec3, end: Vec3, color: Color) -> Vec<(Vec3, Color
 }
 
 
-pub fn set_line(mut start: Vec3, mut end: Vec3, color: Color) -> Vec<(Vec3, Color)> {
+pub fn set_line(mut start: Vec3, mut end: Vec3, color: Color) -> Vec<Surfel> {
     let mut points = vec![];
 
     // probably should do sutherland-hodgeman if this were more serious.
@@ -55,12 +55,11 @@ pub fn set_line(mut start: Vec3, mut end: Vec3, color: Color) -> Vec<(Vec3, Colo
     //let x0 = x0.max(0).min(self.width as isize);
     //let y0 = y0.max(0).min(self.height as isize);
     for (x, y, z) in line_drawing::Bresenham3d::new((start.x as i32, start.y as i32, start.z as i32), (end.x as i32, end.y as i32, end.z as i32)) {
-        points.push(
-            (
-                Vec3::new(x as f32, y as f32, z as f32),
-                color
-            )
-        );
+        points.push(Surfel::new(
+            Vec3::new(x as f32, y as f32, z as f32),
+            color,
+            Vec3::ZERO,
+        ));
     };
     points
 }
\ No newline at end of file
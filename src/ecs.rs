@@ -0,0 +1,264 @@
+use std::{any::{Any, TypeId}, collections::HashMap};
+
+
+/// Handle to an entity. Stale handles to despawned entities never match a reused slot,
+/// as the generation is bumped every time a slot is freed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Entity {
+    pub index: u32,
+    pub generation: u32,
+}
+
+
+/// Components of one type, indexed by entity slot.
+pub struct Storage<T> {
+    items: Vec<Option<(Entity, T)>>,
+    len: usize,
+}
+
+impl<T> Default for Storage<T> {
+    fn default() -> Self {
+        Storage {
+            items: vec![],
+            len: 0,
+        }
+    }
+}
+
+impl<T> Storage<T> {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the component the entity had before, if any.
+    pub fn insert(&mut self, entity: Entity, component: T) -> Option<T> {
+        let idx = entity.index as usize;
+        if idx >= self.items.len() {
+            self.items.resize_with(idx + 1, || None);
+        };
+        let old = self.items[idx].replace((entity, component));
+        match old {
+            Some((owner, old)) if owner == entity => Some(old),
+            Some(_) => None,
+            None => {
+                self.len += 1;
+                None
+            },
+        }
+    }
+
+    pub fn remove(&mut self, entity: Entity) -> Option<T> {
+        let slot = self.items.get_mut(entity.index as usize)?;
+        match slot {
+            Some((owner, _)) if *owner == entity => {
+                self.len -= 1;
+                slot.take().map(|(_, component)| component)
+            },
+            _ => None,
+        }
+    }
+
+    pub fn get(&self, entity: Entity) -> Option<&T> {
+        match self.items.get(entity.index as usize) {
+            Some(Some((owner, component))) if *owner == entity => Some(component),
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, entity: Entity) -> Option<&mut T> {
+        match self.items.get_mut(entity.index as usize) {
+            Some(Some((owner, component))) if *owner == entity => Some(component),
+            _ => None,
+        }
+    }
+
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.get(entity).is_some()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, &T)> {
+        self.items.iter().flatten().map(|(entity, component)| (*entity, component))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Entity, &mut T)> {
+        self.items.iter_mut().flatten().map(|(entity, component)| (*entity, component))
+    }
+}
+
+
+/// Lets `Ecs` clean up after a despawned entity without knowing the component type.
+trait AnyStorage {
+    fn remove_entity(&mut self, entity: Entity);
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: 'static> AnyStorage for Storage<T> {
+    fn remove_entity(&mut self, entity: Entity) {
+        self.remove(entity);
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+
+/// Entities with any number of typed components, one storage per component type.
+#[derive(Default)]
+pub struct Ecs {
+    generations: Vec<u32>,
+    alive: Vec<bool>,
+    free: Vec<u32>,
+    storages: HashMap<TypeId, Box<dyn AnyStorage>>,
+}
+
+impl Ecs {
+    pub fn new() -> Self {
+        Ecs::default()
+    }
+
+    pub fn spawn(&mut self) -> Entity {
+        match self.free.pop() {
+            Some(index) => {
+                self.alive[index as usize] = true;
+                Entity {index, generation: self.generations[index as usize]}
+            },
+            None => {
+                self.generations.push(0);
+                self.alive.push(true);
+                Entity {index: self.generations.len() as u32 - 1, generation: 0}
+            },
+        }
+    }
+
+    /// Removes the entity with all of its components. Returns false if it was already gone.
+    pub fn despawn(&mut self, entity: Entity) -> bool {
+        if !self.is_alive(entity) {
+            return false;
+        };
+        for storage in self.storages.values_mut() {
+            storage.remove_entity(entity);
+        };
+        let idx = entity.index as usize;
+        self.alive[idx] = false;
+        self.generations[idx] = self.generations[idx].wrapping_add(1);
+        self.free.push(entity.index);
+        true
+    }
+
+    pub fn is_alive(&self, entity: Entity) -> bool {
+        let idx = entity.index as usize;
+        self.alive.get(idx).copied().unwrap_or(false) && self.generations[idx] == entity.generation
+    }
+
+    pub fn entities(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.alive.iter()
+            .enumerate()
+            .filter(|(_, alive)| **alive)
+            .map(|(idx, _)| Entity {index: idx as u32, generation: self.generations[idx]})
+    }
+
+    /// Adds or replaces a component. Does nothing for dead entities.
+    pub fn insert<T: 'static>(&mut self, entity: Entity, component: T) {
+        if self.is_alive(entity) {
+            self.storage_mut::<T>().insert(entity, component);
+        };
+    }
+
+    pub fn remove<T: 'static>(&mut self, entity: Entity) -> Option<T> {
+        self.storages.get_mut(&TypeId::of::<T>())?
+            .as_any_mut()
+            .downcast_mut::<Storage<T>>()?
+            .remove(entity)
+    }
+
+    pub fn get<T: 'static>(&self, entity: Entity) -> Option<&T> {
+        self.storage::<T>()?.get(entity)
+    }
+
+    pub fn get_mut<T: 'static>(&mut self, entity: Entity) -> Option<&mut T> {
+        self.storages.get_mut(&TypeId::of::<T>())?
+            .as_any_mut()
+            .downcast_mut::<Storage<T>>()?
+            .get_mut(entity)
+    }
+
+    pub fn has<T: 'static>(&self, entity: Entity) -> bool {
+        self.get::<T>(entity).is_some()
+    }
+
+    pub fn storage<T: 'static>(&self) -> Option<&Storage<T>> {
+        self.storages.get(&TypeId::of::<T>())?
+            .as_any()
+            .downcast_ref::<Storage<T>>()
+    }
+
+    /// Storage for `T`, created empty if no entity had a `T` yet.
+    pub fn storage_mut<T: 'static>(&mut self) -> &mut Storage<T> {
+        self.storages.entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(Storage::<T>::default()))
+            .as_any_mut()
+            .downcast_mut::<Storage<T>>()
+            .expect("storage registered under the wrong type")
+    }
+
+    /// Entities that have a `T`, collected so components can be changed while going through them.
+    pub fn with<T: 'static>(&self) -> Vec<Entity> {
+        match self.storage::<T>() {
+            Some(storage) => storage.iter().map(|(entity, _)| entity).collect(),
+            None => vec![],
+        }
+    }
+}
+
+
+/// Runs once per tick from `World::update`.
+pub type System = fn(&mut Ecs, f32);
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reused_slot_bumps_generation() {
+        let mut ecs = Ecs::new();
+        let first = ecs.spawn();
+        ecs.insert(first, 1.5f32);
+        assert!(ecs.despawn(first));
+        assert!(!ecs.despawn(first));
+
+        let second = ecs.spawn();
+        assert_eq!(second.index, first.index);
+        assert_eq!(second.generation, first.generation + 1);
+        assert!(!ecs.is_alive(first));
+        assert!(ecs.is_alive(second));
+        // The old handle sees nothing, and the component went with the old entity
+        assert_eq!(ecs.get::<f32>(first), None);
+        assert_eq!(ecs.get::<f32>(second), None);
+        ecs.insert(first, 2.5f32);
+        assert!(!ecs.has::<f32>(second));
+    }
+
+    #[test]
+    fn components_by_type() {
+        let mut ecs = Ecs::new();
+        let (a, b) = (ecs.spawn(), ecs.spawn());
+        ecs.insert(a, 1u32);
+        ecs.insert(b, 2u32);
+        ecs.insert(b, "b");
+        assert_eq!(ecs.with::<u32>().len(), 2);
+        assert_eq!(ecs.with::<&str>(), vec![b]);
+        assert_eq!(ecs.remove::<u32>(a), Some(1));
+        assert_eq!(ecs.get::<u32>(b), Some(&2));
+    }
+}
@@ -0,0 +1,67 @@
+use glam::{Vec2, Vec3, Mat3};
+
+use crate::aabb::Aabb;
+
+
+/// Plane in the form `normal.dot(p) + d = 0`. Points with a positive distance are in front of it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Plane {
+    pub normal: Vec3,
+    pub d: f32,
+}
+
+impl Plane {
+    pub fn new(normal: Vec3, d: f32) -> Self {
+        Plane {normal, d}
+    }
+
+    pub fn distance(&self, pt: Vec3) -> f32 {
+        self.normal.dot(pt) + self.d
+    }
+}
+
+
+/// Convex view volume bounded by planes that all face inwards.
+#[derive(Clone, Debug)]
+pub struct Frustum {
+    pub planes: Vec<Plane>,
+}
+
+impl Frustum {
+    pub fn new(planes: Vec<Plane>) -> Self {
+        Frustum {planes}
+    }
+
+    /// Volume seen by an orthographic camera whose view transform is `rotation * p - camera_pos`
+    /// and whose screen covers `min..=max` in view space. Depth is unbounded.
+    pub fn orthographic(rotation: Mat3, camera_pos: Vec3, min: Vec2, max: Vec2) -> Self {
+        let rows = rotation.transpose();
+        Frustum {
+            planes: vec![
+                Plane::new(rows.x_axis, -camera_pos.x - min.x),
+                Plane::new(-rows.x_axis, camera_pos.x + max.x),
+                Plane::new(rows.y_axis, -camera_pos.y - min.y),
+                Plane::new(-rows.y_axis, camera_pos.y + max.y),
+            ],
+        }
+    }
+
+    pub fn contains_point(&self, pt: Vec3) -> bool {
+        self.planes.iter().all(|plane| plane.distance(pt) >= 0.0)
+    }
+
+    /// Conservative test: may report boxes near the corners of the volume as visible, never the other way round.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        if aabb.is_empty() {
+            return false;
+        };
+        for plane in self.planes.iter() {
+            // The corner furthest along the plane normal
+            let positive = Vec3::select(plane.normal.cmpge(Vec3::ZERO), aabb.max, aabb.min);
+            if plane.distance(positive) < 0.0 {
+                return false;
+            };
+        };
+        true
+    }
+}
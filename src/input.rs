@@ -0,0 +1,446 @@
+use std::{collections::HashMap, fmt, path::Path};
+
+use winit::event::VirtualKeyCode;
+use winit_input_helper::WinitInputHelper;
+
+
+/// Physical input that can drive an action or axis.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Binding {
+    Key(VirtualKeyCode),
+    /// Mouse button as numbered by `winit_input_helper`: 0 left, 1 right, 2 middle
+    Mouse(usize),
+    WheelUp,
+    WheelDown,
+    /// Horizontal mouse movement in pixels, only meaningful for axes
+    MouseX,
+    /// Vertical mouse movement in pixels, only meaningful for axes
+    MouseY,
+}
+
+impl Binding {
+    pub fn parse(name: &str) -> Option<Binding> {
+        match name {
+            "WheelUp" => Some(Binding::WheelUp),
+            "WheelDown" => Some(Binding::WheelDown),
+            "MouseX" => Some(Binding::MouseX),
+            "MouseY" => Some(Binding::MouseY),
+            "MouseLeft" => Some(Binding::Mouse(0)),
+            "MouseRight" => Some(Binding::Mouse(1)),
+            "MouseMiddle" => Some(Binding::Mouse(2)),
+            _ => {
+                if let Some(button) = name.strip_prefix("Mouse") {
+                    return button.parse().ok().map(Binding::Mouse);
+                };
+                KEY_NAMES.iter()
+                    .find(|(key_name, _)| *key_name == name)
+                    .map(|(_, key)| Binding::Key(*key))
+            },
+        }
+    }
+
+    /// Current value: `1` or `0` for buttons, the amount moved for the wheel and the mouse.
+    fn value(&self, input: &WinitInputHelper) -> f32 {
+        match self {
+            Binding::Key(key) => input.key_held(*key) as u8 as f32,
+            Binding::Mouse(button) => input.mouse_held(*button) as u8 as f32,
+            Binding::WheelUp => input.scroll_diff().max(0.0),
+            Binding::WheelDown => (-input.scroll_diff()).max(0.0),
+            Binding::MouseX => input.mouse_diff().0,
+            Binding::MouseY => input.mouse_diff().1,
+        }
+    }
+
+    /// Wheel and mouse movement report a change since the last frame rather than a state.
+    pub fn is_relative(&self) -> bool {
+        matches!(self, Binding::WheelUp | Binding::WheelDown | Binding::MouseX | Binding::MouseY)
+    }
+
+    fn pressed(&self, input: &WinitInputHelper) -> bool {
+        match self {
+            Binding::Key(key) => input.key_pressed(*key),
+            Binding::Mouse(button) => input.mouse_pressed(*button),
+            Binding::WheelUp => input.scroll_diff() > 0.0,
+            Binding::WheelDown => input.scroll_diff() < 0.0,
+            Binding::MouseX | Binding::MouseY => false,
+        }
+    }
+
+    fn released(&self, input: &WinitInputHelper) -> bool {
+        match self {
+            Binding::Key(key) => input.key_released(*key),
+            Binding::Mouse(button) => input.mouse_released(*button),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            // Written through the same table `parse` reads, which has every key in it
+            Binding::Key(key) => match KEY_NAMES.iter().find(|(_, k)| k == key) {
+                Some((name, _)) => write!(f, "{}", name),
+                None => write!(f, "{:?}", key),
+            },
+            Binding::Mouse(button) => write!(f, "Mouse{}", button),
+            Binding::WheelUp => write!(f, "WheelUp"),
+            Binding::WheelDown => write!(f, "WheelDown"),
+            Binding::MouseX => write!(f, "MouseX"),
+            Binding::MouseY => write!(f, "MouseY"),
+        }
+    }
+}
+
+
+#[derive(Debug)]
+pub enum InputMapError {
+    Io(std::io::Error),
+    Parse {line: usize, message: String},
+}
+
+impl fmt::Display for InputMapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputMapError::Io(e) => write!(f, "{}", e),
+            InputMapError::Parse {line, message} => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl std::error::Error for InputMapError {}
+
+impl From<std::io::Error> for InputMapError {
+    fn from(e: std::io::Error) -> Self {
+        InputMapError::Io(e)
+    }
+}
+
+
+/// Maps named actions and axes to bindings.
+///
+/// The text format has one action or axis per line:
+/// ```text
+/// # Actions fire when any of their bindings does
+/// action quit = Escape
+/// action select = MouseLeft
+/// # Axes sum their bindings, each scaled by the number before it
+/// axis move_x = -1 A, 1 D
+/// axis zoom = 1 WheelUp, -1 WheelDown
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InputMap {
+    actions: HashMap<String, Vec<Binding>>,
+    axes: HashMap<String, Vec<(Binding, f32)>>,
+}
+
+impl InputMap {
+    pub fn new() -> Self {
+        InputMap::default()
+    }
+
+    /// Bindings used when no config file is present.
+    pub fn with_defaults() -> Self {
+        let mut map = InputMap::new();
+        map.bind_action("quit", Binding::Key(VirtualKeyCode::Escape));
+        map.bind_action("pause", Binding::Key(VirtualKeyCode::Space));
+        map.bind_action("select", Binding::Mouse(0));
+        map.bind_action("orbit", Binding::Mouse(1));
+        map.bind_action("pan", Binding::Mouse(2));
+        map.bind_action("toggle_auto_rotate", Binding::Key(VirtualKeyCode::R));
+        map.bind_action("save_scene", Binding::Key(VirtualKeyCode::F5));
+        map.bind_action("export_ply", Binding::Key(VirtualKeyCode::F6));
+        map.bind_action("toggle_scale_mode", Binding::Key(VirtualKeyCode::F7));
+        map.bind_action("resolution_up", Binding::Key(VirtualKeyCode::Equals));
+        map.bind_action("resolution_down", Binding::Key(VirtualKeyCode::Minus));
+        map.bind_axis("move_x", Binding::Key(VirtualKeyCode::A), -1.0);
+        map.bind_axis("move_x", Binding::Key(VirtualKeyCode::D), 1.0);
+        map.bind_axis("move_y", Binding::Key(VirtualKeyCode::W), -1.0);
+        map.bind_axis("move_y", Binding::Key(VirtualKeyCode::S), 1.0);
+        map.bind_axis("pan_x", Binding::Key(VirtualKeyCode::Left), -1.0);
+        map.bind_axis("pan_x", Binding::Key(VirtualKeyCode::Right), 1.0);
+        map.bind_axis("pan_y", Binding::Key(VirtualKeyCode::Up), -1.0);
+        map.bind_axis("pan_y", Binding::Key(VirtualKeyCode::Down), 1.0);
+        map.bind_axis("look_x", Binding::MouseX, 1.0);
+        map.bind_axis("look_y", Binding::MouseY, 1.0);
+        map.bind_axis("zoom", Binding::WheelUp, 1.0);
+        map.bind_axis("zoom", Binding::WheelDown, -1.0);
+        map
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, InputMapError> {
+        let text = std::fs::read_to_string(path)?;
+        InputMap::parse(&text)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), InputMapError> {
+        std::fs::write(path, self.to_string())?;
+        Ok(())
+    }
+
+    pub fn parse(text: &str) -> Result<Self, InputMapError> {
+        let mut map = InputMap::new();
+        for (idx, line) in text.lines().enumerate() {
+            let line_nr = idx + 1;
+            let error = |message: String| InputMapError::Parse {line: line_nr, message};
+
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            };
+            let (head, bindings) = line.split_once('=')
+                .ok_or_else(|| error("expected `=`".to_string()))?;
+            let mut head = head.split_whitespace();
+            let (kind, name) = match (head.next(), head.next(), head.next()) {
+                (Some(kind), Some(name), None) => (kind, name),
+                _ => return Err(error("expected `action <name>` or `axis <name>`".to_string())),
+            };
+
+            for binding in bindings.split(',').map(|b| b.trim()).filter(|b| !b.is_empty()) {
+                match kind {
+                    "action" => {
+                        let parsed = Binding::parse(binding)
+                            .ok_or_else(|| error(format!("unknown binding `{}`", binding)))?;
+                        map.bind_action(name, parsed);
+                    },
+                    "axis" => {
+                        let (scale, binding) = binding.split_once(char::is_whitespace)
+                            .ok_or_else(|| error(format!("expected `<scale> <binding>`, got `{}`", binding)))?;
+                        let scale: f32 = scale.parse()
+                            .map_err(|_| error(format!("invalid scale `{}`", scale)))?;
+                        let parsed = Binding::parse(binding.trim())
+                            .ok_or_else(|| error(format!("unknown binding `{}`", binding.trim())))?;
+                        map.bind_axis(name, parsed, scale);
+                    },
+                    _ => return Err(error(format!("unknown kind `{}`", kind))),
+                };
+            };
+        };
+        Ok(map)
+    }
+
+
+    pub fn bind_action(&mut self, action: &str, binding: Binding) {
+        let bindings = self.actions.entry(action.to_string()).or_default();
+        if !bindings.contains(&binding) {
+            bindings.push(binding);
+        };
+    }
+
+    pub fn unbind_action(&mut self, action: &str, binding: Binding) {
+        if let Some(bindings) = self.actions.get_mut(action) {
+            bindings.retain(|b| *b != binding);
+        };
+    }
+
+    pub fn bind_axis(&mut self, axis: &str, binding: Binding, scale: f32) {
+        let bindings = self.axes.entry(axis.to_string()).or_default();
+        bindings.retain(|(b, _)| *b != binding);
+        bindings.push((binding, scale));
+    }
+
+    pub fn unbind_axis(&mut self, axis: &str, binding: Binding) {
+        if let Some(bindings) = self.axes.get_mut(axis) {
+            bindings.retain(|(b, _)| *b != binding);
+        };
+    }
+
+    /// Removes every binding of an action or axis with this name.
+    pub fn clear(&mut self, name: &str) {
+        self.actions.remove(name);
+        self.axes.remove(name);
+    }
+
+    pub fn action_bindings(&self, action: &str) -> &[Binding] {
+        self.actions.get(action).map_or(&[], |bindings| bindings.as_slice())
+    }
+
+    pub fn axis_bindings(&self, axis: &str) -> &[(Binding, f32)] {
+        self.axes.get(axis).map_or(&[], |bindings| bindings.as_slice())
+    }
+
+    /// Evaluates all actions and axes against the input of the current frame.
+    pub fn poll(&self, input: &WinitInputHelper) -> Actions {
+        let mut actions = Actions::default();
+        for (name, bindings) in self.actions.iter() {
+            let state = ActionState {
+                pressed: bindings.iter().any(|b| b.pressed(input)),
+                held: bindings.iter().any(|b| b.value(input) != 0.0),
+                released: bindings.iter().any(|b| b.released(input)),
+            };
+            actions.actions.insert(name.clone(), state);
+        };
+        for (name, bindings) in self.axes.iter() {
+            let mut state = AxisState::default();
+            for (binding, scale) in bindings {
+                match binding.is_relative() {
+                    true => state.delta += binding.value(input) * scale,
+                    false => state.level += binding.value(input) * scale,
+                };
+            };
+            actions.axes.insert(name.clone(), state);
+        };
+        actions
+    }
+}
+
+impl fmt::Display for InputMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Sorted so saved files do not reorder themselves on every save
+        let mut actions: Vec<_> = self.actions.iter().collect();
+        actions.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, bindings) in actions {
+            let bindings: Vec<String> = bindings.iter().map(|b| b.to_string()).collect();
+            writeln!(f, "action {} = {}", name, bindings.join(", "))?;
+        };
+        let mut axes: Vec<_> = self.axes.iter().collect();
+        axes.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, bindings) in axes {
+            let bindings: Vec<String> = bindings.iter().map(|(b, scale)| format!("{} {}", scale, b)).collect();
+            writeln!(f, "axis {} = {}", name, bindings.join(", "))?;
+        };
+        Ok(())
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ActionState {
+    pub pressed: bool,
+    pub held: bool,
+    pub released: bool,
+}
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct AxisState {
+    /// Contribution of keys and buttons, which hold their value while held
+    level: f32,
+    /// Contribution of the wheel and mouse movement, which accumulate between ticks
+    delta: f32,
+}
+
+
+/// Snapshot of all actions and axes, handed to the simulation instead of raw input.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Actions {
+    actions: HashMap<String, ActionState>,
+    axes: HashMap<String, AxisState>,
+}
+
+impl Actions {
+    pub fn state(&self, action: &str) -> ActionState {
+        self.actions.get(action).copied().unwrap_or_default()
+    }
+
+    pub fn pressed(&self, action: &str) -> bool {
+        self.state(action).pressed
+    }
+
+    pub fn held(&self, action: &str) -> bool {
+        self.state(action).held
+    }
+
+    pub fn released(&self, action: &str) -> bool {
+        self.state(action).released
+    }
+
+    pub fn axis(&self, axis: &str) -> f32 {
+        self.axes.get(axis).map_or(0.0, |state| state.level + state.delta)
+    }
+
+    /// Folds in a newer snapshot. Presses, releases and wheel or mouse movement are kept
+    /// until `end_tick`, so frames that run no simulation tick do not lose them.
+    pub fn absorb(&mut self, newer: Actions) {
+        for (name, state) in newer.actions {
+            let entry = self.actions.entry(name).or_default();
+            entry.pressed |= state.pressed;
+            entry.released |= state.released;
+            entry.held = state.held;
+        };
+        for (name, state) in newer.axes {
+            let entry = self.axes.entry(name).or_default();
+            entry.level = state.level;
+            entry.delta += state.delta;
+        };
+    }
+
+    /// Clears everything that should only be seen by a single tick.
+    pub fn end_tick(&mut self) {
+        for state in self.actions.values_mut() {
+            state.pressed = false;
+            state.released = false;
+        };
+        for state in self.axes.values_mut() {
+            state.delta = 0.0;
+        };
+    }
+}
+
+
+macro_rules! key_names {
+    ($($key:ident),* $(,)?) => {
+        &[$((stringify!($key), VirtualKeyCode::$key)),*]
+    };
+}
+
+/// Names of all keys in an input map file, spelled like their `VirtualKeyCode` variant.
+const KEY_NAMES: &[(&str, VirtualKeyCode)] = key_names!(
+    Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0,
+    A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    Escape, F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+    F13, F14, F15, F16, F17, F18, F19, F20, F21, F22, F23, F24,
+    Snapshot, Scroll, Pause, Insert, Home, Delete, End, PageDown, PageUp,
+    Left, Up, Right, Down,
+    Back, Return, Space, Tab, Compose, Caret, Numlock, Capital,
+    Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9,
+    NumpadAdd, NumpadSubtract, NumpadMultiply, NumpadDivide, NumpadEnter,
+    NumpadDecimal, NumpadComma, NumpadEquals,
+    LAlt, RAlt, LControl, RControl, LShift, RShift, LWin, RWin,
+    Apostrophe, Backslash, Comma, Equals, Grave, LBracket, RBracket, Minus, Period, Semicolon, Slash,
+    Asterisk, At, Colon, Plus, Underline, Yen, OEM102,
+    AbntC1, AbntC2, Apps, Ax, Convert, Kana, Kanji, NoConvert, Sysrq, Unlabeled,
+    Calculator, Mail, MediaSelect, MediaStop, Mute, MyComputer, NavigateForward, NavigateBackward,
+    NextTrack, PlayPause, Power, PrevTrack, Sleep, Stop, VolumeDown, VolumeUp, Wake,
+    WebBack, WebFavorites, WebForward, WebHome, WebRefresh, WebSearch, WebStop,
+    Copy, Paste, Cut,
+);
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_key_name_round_trips() {
+        for (name, key) in KEY_NAMES {
+            let binding = Binding::Key(*key);
+            assert_eq!(binding.to_string(), *name);
+            assert_eq!(Binding::parse(name), Some(binding));
+        };
+    }
+
+    #[test]
+    fn other_bindings_round_trip() {
+        for binding in [Binding::Mouse(0), Binding::Mouse(5), Binding::WheelUp, Binding::WheelDown, Binding::MouseX, Binding::MouseY] {
+            assert_eq!(Binding::parse(&binding.to_string()), Some(binding));
+        };
+    }
+
+    #[test]
+    fn map_round_trips() {
+        let map = InputMap::with_defaults();
+        assert_eq!(InputMap::parse(&map.to_string()).unwrap(), map);
+    }
+
+    #[test]
+    fn parse_reports_line() {
+        let text = "# comment\naction quit = Escape\naction jump = NoSuchKey\n";
+        match InputMap::parse(text) {
+            Err(InputMapError::Parse {line, ..}) => assert_eq!(line, 3),
+            other => panic!("expected a parse error, got {:?}", other),
+        };
+        assert!(InputMap::parse("axis move_x = A").is_err());
+        assert!(InputMap::parse("button quit = Escape").is_err());
+    }
+}
@@ -0,0 +1,474 @@
+use std::fmt;
+
+
+/// Arrays and objects nested deeper than this are rejected rather than overflowing the stack
+const MAX_DEPTH: usize = 128;
+
+/// Parsed JSON value. Objects keep their keys in file order, so saved files diff nicely.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct JsonError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for JsonError {}
+
+
+impl Json {
+    pub fn parse(text: &str) -> Result<Json, JsonError> {
+        let mut parser = Parser {chars: text.chars().collect(), pos: 0, depth: 0};
+        parser.skip_whitespace();
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        match parser.peek() {
+            Some(c) => Err(parser.error(format!("unexpected '{}' after the end of the document", c))),
+            None => Ok(value),
+        }
+    }
+
+    /// Value stored under `key`, if this is an object that has it.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Json::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_f32(&self) -> Option<f32> {
+        self.as_f64().map(|n| n as f32)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&[(String, Json)]> {
+        match self {
+            Json::Object(fields) => Some(fields),
+            _ => None,
+        }
+    }
+
+    fn write(&self, f: &mut fmt::Formatter<'_>, indent: usize) -> fmt::Result {
+        // Arrays of plain values such as vectors and colours stay on one line
+        let flat = |items: &[Json]| items.iter().all(|item| !matches!(item, Json::Array(_) | Json::Object(_)));
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Number(n) => match n.is_finite() {
+                true => write!(f, "{}", n),
+                false => write!(f, "null"),
+            },
+            Json::String(s) => write_string(f, s),
+            Json::Array(items) if items.is_empty() => write!(f, "[]"),
+            Json::Array(items) if flat(items) => {
+                write!(f, "[")?;
+                for (idx, item) in items.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ", ")?;
+                    };
+                    item.write(f, indent)?;
+                };
+                write!(f, "]")
+            },
+            Json::Array(items) => {
+                writeln!(f, "[")?;
+                for (idx, item) in items.iter().enumerate() {
+                    write!(f, "{:width$}", "", width = (indent + 1) * 2)?;
+                    item.write(f, indent + 1)?;
+                    match idx + 1 < items.len() {
+                        true => writeln!(f, ",")?,
+                        false => writeln!(f)?,
+                    };
+                };
+                write!(f, "{:width$}]", "", width = indent * 2)
+            },
+            Json::Object(fields) if fields.is_empty() => write!(f, "{{}}"),
+            Json::Object(fields) => {
+                writeln!(f, "{{")?;
+                for (idx, (key, value)) in fields.iter().enumerate() {
+                    write!(f, "{:width$}", "", width = (indent + 1) * 2)?;
+                    write_string(f, key)?;
+                    write!(f, ": ")?;
+                    value.write(f, indent + 1)?;
+                    match idx + 1 < fields.len() {
+                        true => writeln!(f, ",")?,
+                        false => writeln!(f)?,
+                    };
+                };
+                write!(f, "{:width$}}}", "", width = indent * 2)
+            },
+        }
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+impl From<f32> for Json {
+    fn from(n: f32) -> Self {
+        // Going through the shortest text form keeps 0.3 from turning into 0.30000001192092896
+        Json::Number(n.to_string().parse().unwrap_or(n as f64))
+    }
+}
+
+impl From<bool> for Json {
+    fn from(b: bool) -> Self {
+        Json::Bool(b)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(s: &str) -> Self {
+        Json::String(s.to_string())
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        };
+    };
+    write!(f, "\"")
+}
+
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    /// Arrays and objects currently open
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.pos += 1;
+        c
+    }
+
+    fn error(&self, message: String) -> JsonError {
+        let before = &self.chars[..self.pos.min(self.chars.len())];
+        let line = before.iter().filter(|c| **c == '\n').count() + 1;
+        let column = before.iter().rev().take_while(|c| **c != '\n').count() + 1;
+        JsonError {line, column, message}
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t' | '\n' | '\r')) {
+            self.pos += 1;
+        };
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), JsonError> {
+        match self.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => {
+                self.pos -= 1;
+                Err(self.error(format!("expected '{}', found '{}'", expected, c)))
+            },
+            None => Err(self.error(format!("expected '{}', found the end of the document", expected))),
+        }
+    }
+
+    fn keyword(&mut self, word: &str, value: Json) -> Result<Json, JsonError> {
+        for expected in word.chars() {
+            self.expect(expected)?;
+        };
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Json, JsonError> {
+        match self.peek() {
+            Some(c @ ('{' | '[')) => {
+                if self.depth >= MAX_DEPTH {
+                    return Err(self.error(format!("nested deeper than {} levels", MAX_DEPTH)));
+                };
+                self.depth += 1;
+                let value = match c {
+                    '{' => self.object(),
+                    _ => self.array(),
+                };
+                self.depth -= 1;
+                value
+            },
+            Some('"') => self.string().map(Json::String),
+            Some('t') => self.keyword("true", Json::Bool(true)),
+            Some('f') => self.keyword("false", Json::Bool(false)),
+            Some('n') => self.keyword("null", Json::Null),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            Some(c) => Err(self.error(format!("unexpected '{}'", c))),
+            None => Err(self.error("unexpected end of the document".to_string())),
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, JsonError> {
+        self.expect('{')?;
+        let mut fields = vec![];
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.pos += 1;
+            return Ok(Json::Object(fields));
+        };
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            self.skip_whitespace();
+            fields.push((key, self.value()?));
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => continue,
+                Some('}') => return Ok(Json::Object(fields)),
+                _ => {
+                    self.pos -= 1;
+                    return Err(self.error("expected ',' or '}'".to_string()));
+                },
+            };
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, JsonError> {
+        self.expect('[')?;
+        let mut items = vec![];
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.pos += 1;
+            return Ok(Json::Array(items));
+        };
+        loop {
+            self.skip_whitespace();
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => continue,
+                Some(']') => return Ok(Json::Array(items)),
+                _ => {
+                    self.pos -= 1;
+                    return Err(self.error("expected ',' or ']'".to_string()));
+                },
+            };
+        }
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(s),
+                Some('\\') => {
+                    let c = match self.next() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => self.unicode_escape()?,
+                        _ => return Err(self.error("invalid escape".to_string())),
+                    };
+                    s.push(c);
+                },
+                Some(c) => s.push(c),
+                None => return Err(self.error("unterminated string".to_string())),
+            };
+        }
+    }
+
+    /// The part of a `\u` escape after the `u`. Characters outside the basic plane come as a
+    /// UTF-16 surrogate pair of two escapes.
+    fn unicode_escape(&mut self) -> Result<char, JsonError> {
+        let high = self.hex4()?;
+        let code = match high {
+            0xD800..=0xDBFF => {
+                if self.next() != Some('\\') || self.next() != Some('u') {
+                    return Err(self.error(format!("unpaired surrogate \\u{:04X}", high)));
+                };
+                let low = self.hex4()?;
+                if !(0xDC00..=0xDFFF).contains(&low) {
+                    return Err(self.error(format!("invalid surrogate pair \\u{:04X}\\u{:04X}", high, low)));
+                };
+                0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+            },
+            0xDC00..=0xDFFF => return Err(self.error(format!("unpaired surrogate \\u{:04X}", high))),
+            code => code,
+        };
+        char::from_u32(code).ok_or_else(|| self.error(format!("invalid code point {:X}", code)))
+    }
+
+    fn hex4(&mut self) -> Result<u32, JsonError> {
+        let hex: String = (0..4).filter_map(|_| self.next()).collect();
+        match hex.len() == 4 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            true => Ok(u32::from_str_radix(&hex, 16).unwrap_or(0)),
+            false => Err(self.error(format!("invalid escape \\u{}", hex))),
+        }
+    }
+
+    /// Number of ASCII digits skipped.
+    fn digits(&mut self) -> usize {
+        let start = self.pos;
+        while matches!(self.peek(), Some(c) if c.is_ascii_digit()) {
+            self.pos += 1;
+        };
+        self.pos - start
+    }
+
+    /// `-? (0 | [1-9][0-9]*) (.[0-9]+)? ([eE][+-]?[0-9]+)?`, so no leading zeros, `+`, `.5` or `1.`
+    fn number(&mut self) -> Result<Json, JsonError> {
+        let start = self.pos;
+        if self.peek() == Some('-') {
+            self.pos += 1;
+        };
+        let valid = match self.peek() {
+            Some('0') => {
+                self.pos += 1;
+                true
+            },
+            _ => self.digits() > 0,
+        };
+        let valid = valid && match self.peek() {
+            Some('.') => {
+                self.pos += 1;
+                self.digits() > 0
+            },
+            _ => true,
+        };
+        let valid = valid && match self.peek() {
+            Some('e' | 'E') => {
+                self.pos += 1;
+                if matches!(self.peek(), Some('+' | '-')) {
+                    self.pos += 1;
+                };
+                self.digits() > 0
+            },
+            _ => true,
+        };
+        // Whatever is glued on belongs to the malformed number, not to the next token
+        let end = self.pos;
+        while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || matches!(c, '-' | '+' | '.')) {
+            self.pos += 1;
+        };
+        let text: String = self.chars[start..self.pos].iter().collect();
+        match text.parse::<f64>() {
+            Ok(n) if valid && self.pos == end && n.is_finite() => Ok(Json::Number(n)),
+            _ => {
+                self.pos = start;
+                Err(self.error(format!("invalid number '{}'", text)))
+            },
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let text = r#"{"name": "box", "size": [1, -2.5, 3e2], "tags": [], "nested": {"on": true, "off": false, "none": null}, "text": "a\"b\\c\nd\u0001"}"#;
+        let parsed = Json::parse(text).unwrap();
+        assert_eq!(parsed.get("size").and_then(|s| s.as_array()).map(|s| s.len()), Some(3));
+        assert_eq!(parsed.get("text").and_then(|s| s.as_str()), Some("a\"b\\c\nd\u{1}"));
+        assert_eq!(Json::parse(&parsed.to_string()).unwrap(), parsed);
+    }
+
+    #[test]
+    fn surrogate_pairs() {
+        assert_eq!(Json::parse(r#""\uD83D\uDE00""#).unwrap(), Json::String("\u{1F600}".to_string()));
+        assert_eq!(Json::parse(r#""\u00e9\u00C9""#).unwrap(), Json::String("éÉ".to_string()));
+        assert!(Json::parse(r#""\uD83D""#).is_err());
+        assert!(Json::parse(r#""\uD83Dx""#).is_err());
+        assert!(Json::parse(r#""\uD83DA""#).is_err());
+        assert!(Json::parse(r#""\uDE00""#).is_err());
+        assert!(Json::parse(r#""\u+041""#).is_err());
+    }
+
+    #[test]
+    fn number_grammar() {
+        for (text, value) in [("0", 0.0), ("-0", 0.0), ("12", 12.0), ("-1.5", -1.5), ("2e3", 2000.0), ("2E-1", 0.2), ("0.5e+1", 5.0)] {
+            assert_eq!(Json::parse(text).unwrap(), Json::Number(value), "{}", text);
+        };
+        for text in ["01", "+1", ".5", "1.", "-", "1e", "1e+", "--1", "1.2.3", "1-2", "0x10", "1e999", "[1 2]"] {
+            assert!(Json::parse(text).is_err(), "{} should be rejected", text);
+        };
+        assert_eq!(Json::parse("[1,-2]").unwrap(), Json::Array(vec![Json::Number(1.0), Json::Number(-2.0)]));
+    }
+
+    #[test]
+    fn depth_limit() {
+        let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(Json::parse(&nested(MAX_DEPTH)).is_ok());
+        assert!(Json::parse(&nested(MAX_DEPTH + 1)).is_err());
+        // Deep enough to overflow the stack without the limit
+        assert!(Json::parse(&"[".repeat(1_000_000)).is_err());
+        assert!(Json::parse(&"{\"a\":".repeat(1_000)).is_err());
+    }
+
+    #[test]
+    fn error_position() {
+        let error = Json::parse("{\n  \"a\": 1,\n  \"b\" 2\n}").unwrap_err();
+        assert_eq!((error.line, error.column), (3, 7));
+    }
+}
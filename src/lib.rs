@@ -1,8 +1,34 @@
-
 pub mod cell;
 pub mod color;
 use color::Color;
+pub mod aabb;
+pub mod ray;
+pub mod bvh;
+pub mod collision;
+pub mod physics;
 pub mod drawable;
+pub mod frustum;
+pub mod picking;
 use drawable::Drawable;
 pub mod boxshape;
-pub mod drawutil;
\ No newline at end of file
+pub mod drawutil;
+pub mod surfel;
+pub mod rng;
+pub mod noise;
+pub mod timestep;
+pub mod input;
+pub mod camera;
+pub mod player;
+pub mod ecs;
+pub mod components;
+pub mod scene;
+pub mod json;
+pub mod scenefile;
+pub mod watch;
+pub mod pointcloud;
+pub mod ply;
+pub mod cli;
+pub mod screenshot;
+pub mod viewport;
+pub mod raster;
+pub mod world;
\ No newline at end of file
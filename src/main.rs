@@ -1,31 +1,84 @@
 #![deny(clippy::all)]
 #![forbid(unsafe_code)]
 
-use glam::{Vec3, Mat3};
-use log::{debug, error};
+use std::{path::PathBuf, time::Duration};
+
+use glam::Vec2;
+use log::{debug, error, info};
 use pixels::{Error, Pixels, SurfaceTexture};
 use winit::{
     dpi::LogicalSize,
-    event::{Event, VirtualKeyCode},
+    event::Event,
     event_loop::{ControlFlow, EventLoop},
     window::WindowBuilder,
 };
 use winit_input_helper::WinitInputHelper;
-use topdown::{drawable::Drawable, boxshape::BoxShape, color::Color};
+use topdown::{drawable::Drawable, cli::{CliError, Options, USAGE}, input::{Actions, InputMap, InputMapError}, ply::{self, PlyFormat}, scenefile::{SceneError, SceneFile}, screenshot, timestep::FixedTimestep, viewport::{ScaleMode, Viewport}, watch::FileWatcher, world::World};
 
 
-const WIDTH: u32 = 300;
-const HEIGHT: u32 = 200;
-const SCALE: f64 = 3.0;
+/// Key bindings are read from here if the file exists
+const INPUT_MAP_PATH: &str = "input.cfg";
+/// Scene to start with unless one is given with `--scene`. The copy built into the binary is
+/// used when the file is missing.
+const SCENE_PATH: &str = "scenes/default.json";
+const DEFAULT_SCENE: &str = include_str!("../scenes/default.json");
+/// Where the export action writes all points of the world
+const EXPORT_PLY_PATH: &str = "world.ply";
+/// How often to check the scene file for changes
+const SCENE_POLL_INTERVAL: Duration = Duration::from_millis(500);
 
 fn main() -> Result<(), Error> {
     env_logger::init();
+    let options = match Options::from_env() {
+        Ok(options) => options,
+        Err(CliError::Help) => {
+            println!("{}", USAGE);
+            return Ok(());
+        },
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        },
+    };
+    let (width, height) = (options.width, options.height);
+
+    let scene_path = options.scene.clone().unwrap_or_else(|| PathBuf::from(SCENE_PATH));
+    let scene = match SceneFile::load(&scene_path) {
+        Ok(scene) => scene,
+        Err(e) => {
+            // Only the default scene has a fallback, a scene asked for by name has to be there
+            if options.scene.is_some() {
+                eprintln!("Failed to load {}: {}", scene_path.display(), e);
+                std::process::exit(1);
+            };
+            if !matches!(e, SceneError::Io(_)) {
+                error!("Failed to load {}: {}", scene_path.display(), e);
+            };
+            SceneFile::parse(DEFAULT_SCENE).expect("built-in scene is valid")
+        },
+    };
+    let mut world = match World::from_scene(&scene, width as usize, height as usize) {
+        Ok(world) => world,
+        Err(e) => {
+            eprintln!("Failed to load {}: {}", scene_path.display(), e);
+            std::process::exit(1);
+        },
+    };
+
+    if options.headless {
+        if let Err(e) = run_headless(&mut world, &options) {
+            eprintln!("Failed to write frames to {}: {}", options.out.display(), e);
+            std::process::exit(1);
+        };
+        return Ok(());
+    };
+
     let event_loop = EventLoop::new();
     let mut input = WinitInputHelper::new();
 
     let window = {
-        let size = LogicalSize::new(WIDTH as f64, HEIGHT as f64);
-        let scaled_size = LogicalSize::new(WIDTH as f64 * SCALE, HEIGHT as f64 * SCALE);
+        let size = LogicalSize::new(width as f64, height as f64);
+        let scaled_size = LogicalSize::new(width as f64 * options.scale, height as f64 * options.scale);
         WindowBuilder::new()
             .with_title("Sandbox")
             .with_inner_size(scaled_size)
@@ -37,10 +90,33 @@ fn main() -> Result<(), Error> {
     let mut pixels = {
         let window_size = window.inner_size();
         let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
-        Pixels::new(WIDTH, HEIGHT, surface_texture)?
+        Pixels::new(width, height, surface_texture)?
     };
 
-    let mut world = World::new(WIDTH as usize, HEIGHT as usize);
+    let mode = match options.fit {
+        true => ScaleMode::Fit,
+        false => ScaleMode::Fixed,
+    };
+    let mut viewport = Viewport::new(width, height, options.scale.round() as u32, mode);
+    let window_size = window.inner_size();
+    if viewport.window_resized(window_size.width, window_size.height) {
+        pixels.resize_buffer(viewport.width, viewport.height);
+        world.resize(viewport.width as usize, viewport.height as usize);
+    };
+
+    world.set_id_buffer_enabled(true);
+    let mut scene_watcher = FileWatcher::new(&scene_path, SCENE_POLL_INTERVAL);
+    let mut timestep = FixedTimestep::new(options.tick_rate);
+
+    let input_map = match InputMap::load(INPUT_MAP_PATH) {
+        Ok(map) => map,
+        Err(InputMapError::Io(_)) => InputMap::with_defaults(),
+        Err(e) => {
+            error!("Failed to load {}: {}", INPUT_MAP_PATH, e);
+            InputMap::with_defaults()
+        },
+    };
+    let mut actions = Actions::default();
 
 
     event_loop.run(move |event, _, control_flow| {
@@ -60,15 +136,77 @@ fn main() -> Result<(), Error> {
         // For everything else, for let winit_input_helper collect events to build its state.
         // It returns `true` when it is time to update our game state and request a redraw.
         if input.update(&event) {
-            if input.key_pressed(VirtualKeyCode::Escape) || input.quit() {
+            let frame_actions = input_map.poll(&input);
+            if frame_actions.pressed("quit") || input.quit() {
                 *control_flow = ControlFlow::Exit;
                 return;
             };
-            // Resize the window
+            // Resize the window, and the render target along with it when it follows the window
+            let mut resolution_changed = false;
             if let Some(size) = input.window_resized() {
                 pixels.resize_surface(size.width, size.height);
+                resolution_changed |= viewport.window_resized(size.width, size.height);
+            };
+            if frame_actions.pressed("toggle_scale_mode") {
+                let mode = match viewport.mode {
+                    ScaleMode::Fixed => ScaleMode::Fit,
+                    ScaleMode::Fit => ScaleMode::Fixed,
+                };
+                resolution_changed |= viewport.set_mode(mode);
+                info!("Scale mode {:?}", mode);
+            };
+            if frame_actions.pressed("resolution_up") {
+                resolution_changed |= viewport.change_pixel_scale(-1);
+            };
+            if frame_actions.pressed("resolution_down") {
+                resolution_changed |= viewport.change_pixel_scale(1);
+            };
+            if resolution_changed {
+                pixels.resize_buffer(viewport.width, viewport.height);
+                world.resize(viewport.width as usize, viewport.height as usize);
+                info!("Resolution {}x{}", viewport.width, viewport.height);
+            };
+            if frame_actions.pressed("save_scene") {
+                match world.to_scene().save(&scene_path) {
+                    Ok(()) => info!("Saved the scene to {}", scene_path.display()),
+                    Err(e) => error!("Failed to save {}: {}", scene_path.display(), e),
+                };
+                // Don't reload what we just wrote
+                scene_watcher.mark_seen();
             };
-            world.update();
+            if frame_actions.pressed("export_ply") {
+                let objects: Vec<&dyn Drawable> = world.objects.iter().map(|object| object.as_ref()).collect();
+                match ply::save(EXPORT_PLY_PATH, &objects, PlyFormat::BinaryLittleEndian) {
+                    Ok(()) => info!("Exported the world to {}", EXPORT_PLY_PATH),
+                    Err(e) => error!("Failed to export {}: {}", EXPORT_PLY_PATH, e),
+                };
+            };
+            if scene_watcher.changed() {
+                // A broken file keeps the current world, so a half saved edit doesn't lose anything
+                match SceneFile::load(&scene_path).and_then(|scene| world.reload_scene(&scene)) {
+                    Ok(()) => info!("Reloaded {}", scene_path.display()),
+                    Err(e) => error!("Failed to reload {}: {}", scene_path.display(), e),
+                };
+            };
+            // Select whatever is under the cursor
+            if frame_actions.pressed("select") {
+                if let Some(pos) = input.mouse() {
+                    if let Ok((x, y)) = pixels.window_pos_to_pixel(pos) {
+                        let hit = world.pick(Vec2::new(x as f32, y as f32));
+                        debug!("Picked {:?}", hit);
+                        // The id buffer is exact per pixel, the ray only knows about bounds
+                        world.selected = world.object_at(x, y)
+                            .map(|id| id as usize)
+                            .or(hit.map(|hit| hit.object));
+                    };
+                };
+            };
+            actions.absorb(frame_actions);
+            for _ in 0..timestep.advance() {
+                world.update(timestep.dt(), &actions);
+                actions.end_tick();
+            };
+            world.render_alpha = timestep.alpha();
             //if !paused || input.key_pressed_os(VirtualKeyCode::Space) {
             //    life.update();
             //}
@@ -81,175 +219,18 @@ fn main() -> Result<(), Error> {
 }
 
 
-pub struct World {
-    pub objects: Vec<Box<dyn Drawable>>,
-    pub light_dir: Vec3,
-    pub light_intensity: f32,
-    pub rotation_x: f32,
-    pub camera_pos: Vec3,
-    pub width: usize,
-    pub height: usize,
-    pub time: f32,
+/// Renders `options.frames` frames one tick apart into `options.out`, without a window.
+fn run_headless(world: &mut World, options: &Options) -> std::io::Result<()> {
+    std::fs::create_dir_all(&options.out)?;
+    let mut frame = vec![0u8; world.width * world.height * 4];
+    let actions = Actions::default();
+    let timestep = FixedTimestep::new(options.tick_rate);
+    for idx in 0..options.frames {
+        world.draw(&mut frame);
+        let path = options.out.join(format!("frame{:05}.ppm", idx));
+        screenshot::save_ppm(&path, world.width, world.height, &frame)?;
+        world.update(timestep.dt(), &actions);
+    };
+    info!("Wrote {} frames to {}", options.frames, options.out.display());
+    Ok(())
 }
-
-impl World {
-    pub fn new(width: usize, height: usize) -> World {
-
-
-        World {
-            objects: vec![
-                Box::new(BoxShape::new(
-                    Vec3::new(0.0, -10.0, 0.0),
-                    Vec3::new(10.0, 10.0, 10.0),
-                    Color::rgba(1.0, 0.3, 0.3, 1.0),
-                )),
-                Box::new(BoxShape::new(
-                    Vec3::new(0.0, 0.0, 0.0),
-                    Vec3::new(500.0, 1.0, 500.0),
-                    Color::rgba(0.8, 0.8, 0.8, 1.0),
-                )),
-            ],
-            light_dir: Vec3::new(-0.5, -0.5, 1.0),
-            light_intensity: 1.0,
-            rotation_x: 0.75,
-            camera_pos: Vec3::new(-150.0, -100.0, 0.0),
-            width,
-            height,
-            time: 0.0,
-        }
-    }
-
-    fn grid_idx<I: std::convert::TryInto<usize>>(&self, x: I, y: I) -> Option<usize> {
-        if let (Ok(x), Ok(y)) = (x.try_into(), y.try_into()) {
-            if x < self.width && y < self.height {
-                Some(x + y * self.width)
-            } else {
-                None
-            }
-        } else {
-            None
-        }
-    }
-
-    fn screen_idx<I: std::convert::TryInto<usize>>(&self, x: I, y: I) -> Option<usize> {
-        if let (Ok(x), Ok(y)) = (x.try_into(), y.try_into()) {
-            if x < self.width && y < self.height {
-                Some(x * 4 + y * self.width * 4)
-            } else {
-                None
-            }
-        } else {
-            None
-        }
-    }
-
-
-    fn is_in_bounds(&self, pt: Vec3) -> bool {
-        (pt.x >= -1.0 && pt.x <= WIDTH as f32)
-        && (pt.y >= -1.0 && pt.y <= HEIGHT as f32)
-    }
-
-    pub fn update(&mut self) {
-        self.time += 0.04;
-    }
-
-    pub fn draw(&self, screen: &mut [u8]) {
-        screen.fill(0);
-
-        // (2D Position, Color, Normal)
-        let mut all_points = Vec::<(Vec3, Color, Vec3)>::new();
-
-        for object in self.objects.iter() {
-            let origin = Vec3::new(0.0, 0.0, 0.0);//
-            for (point, color, mut normal) in object.get_points() {
-                //normal = self.rotateY(normal, self.time);
-                // normal += origin;
-                let mut pt_rot = self.rotateY(*point - origin, self.time);
-                pt_rot += origin;
-                //let mut pt_rot = *point;
-                
-                pt_rot = self.rotateX(pt_rot - origin, self.rotation_x);
-                pt_rot += origin;
-                //normal = self.rotateX(normal, self.rotation_x);
-                //normal += origin;
-                
-                pt_rot -= self.camera_pos;
-                let pos_2d = self.project(pt_rot);
-                if self.is_in_bounds(pos_2d) {
-                    all_points.push((pos_2d, *color, normal));
-                };
-            };
-        };
-
-        all_points.sort_by(|a, b| {
-            if a.0.z < b.0.z {
-                return std::cmp::Ordering::Less;
-            } else if a.0.z > b.0.z {
-                return std::cmp::Ordering::Greater;
-            } else {
-                return std::cmp::Ordering::Equal;
-            };
-        });
-        let light_dir_rot = self.rotateY(self.light_dir, 0.0);
-        let mut prev_normal = Vec3::ZERO;
-        let mut prev_ratio = 0.0;
-        for (pos_2d, color, normal) in all_points {
-            
-            let basecolor = color;
-            let normal_ratio = match normal == prev_normal {
-                true => prev_ratio,
-                false => {
-                    let r = normal.dot(light_dir_rot * 1.0) * self.light_intensity;
-                    r.min(1.0).max(0.0)
-                },
-            };
-
-            let mut lighted_color = basecolor;
-            lighted_color *= normal_ratio;
-            
-            let idx = self.screen_idx(pos_2d.x.round() as usize, pos_2d.y.round() as usize);
-
-            if let Some(idx) = idx {
-                let c = lighted_color.as_255();
-                screen[idx + 0] = c.r as u8;
-                screen[idx + 1] = c.g as u8;
-                screen[idx + 2] = c.b as u8;
-                screen[idx + 3] = c.a as u8;
-            };
-
-            prev_normal = normal;
-            prev_ratio = normal_ratio;
-        }
-        
-    }
-
-
-    pub fn project(&self, point: Vec3) -> Vec3 {
-        Mat3::IDENTITY * point
-    }
-
-
-    pub fn rotateX(&self, point: Vec3, angle: f32) -> Vec3 {
-        Mat3::from_cols(
-            Vec3::new(1.0, 0.0, 0.0),
-            Vec3::new(0.0, angle.cos(), -angle.sin()),
-            Vec3::new(0.0, angle.sin(), angle.cos()),
-        ) * point
-    }
-
-    pub fn rotateY(&self, point: Vec3, angle: f32) -> Vec3 {
-        Mat3::from_cols(
-            Vec3::new(angle.cos(), 0.0, angle.sin()),
-            Vec3::new(0.0, 1.0, 0.0),
-            Vec3::new(-angle.sin(), 0.0, angle.cos()),
-        ) * point
-    }
-
-    pub fn rotateZ(&self, point: Vec3, angle: f32) -> Vec3 {
-        Mat3::from_cols(
-            Vec3::new(angle.cos(), -angle.sin(), 0.0),
-            Vec3::new(angle.sin(), angle.cos(), 0.0),
-            Vec3::new(0.0, 0.0, 1.0),
-        ) * point
-    }
-}
\ No newline at end of file
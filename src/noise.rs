@@ -0,0 +1,377 @@
+use glam::{Vec2, Vec3};
+
+use crate::rng::Rng;
+
+
+const GRAD3: [Vec3; 12] = [
+    Vec3::new(1.0, 1.0, 0.0), Vec3::new(-1.0, 1.0, 0.0), Vec3::new(1.0, -1.0, 0.0), Vec3::new(-1.0, -1.0, 0.0),
+    Vec3::new(1.0, 0.0, 1.0), Vec3::new(-1.0, 0.0, 1.0), Vec3::new(1.0, 0.0, -1.0), Vec3::new(-1.0, 0.0, -1.0),
+    Vec3::new(0.0, 1.0, 1.0), Vec3::new(0.0, -1.0, 1.0), Vec3::new(0.0, 1.0, -1.0), Vec3::new(0.0, -1.0, -1.0),
+];
+
+const GRAD2: [Vec2; 8] = [
+    Vec2::new(1.0, 0.0), Vec2::new(-1.0, 0.0), Vec2::new(0.0, 1.0), Vec2::new(0.0, -1.0),
+    Vec2::new(0.70710677, 0.70710677), Vec2::new(-0.70710677, 0.70710677),
+    Vec2::new(0.70710677, -0.70710677), Vec2::new(-0.70710677, -0.70710677),
+];
+
+// Skew factors for simplex noise: (sqrt(n + 1) - 1) / n and (1 - 1 / sqrt(n + 1)) / n
+const F2: f32 = 0.3660254;
+const G2: f32 = 0.21132487;
+const F3: f32 = 1.0 / 3.0;
+const G3: f32 = 1.0 / 6.0;
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NoiseKind {
+    Perlin,
+    Simplex,
+    Worley,
+}
+
+
+/// Seeded gradient and cellular noise.
+///
+/// Perlin and simplex output lies roughly in `[-1, 1]`, Worley output is the distance
+/// to the closest feature point (`0` at a feature point, usually below `1`).
+#[derive(Clone)]
+pub struct Noise {
+    seed: u64,
+    perm: Vec<usize>,
+}
+
+impl Noise {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = Rng::new(seed);
+        let mut perm: Vec<usize> = (0..256).collect();
+        rng.shuffle(&mut perm);
+        // Doubled so that lookups like perm[x + perm[y]] never need wrapping.
+        let doubled = perm.clone();
+        perm.extend(doubled);
+
+        Noise {
+            seed,
+            perm,
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn sample2(&self, kind: NoiseKind, p: Vec2) -> f32 {
+        match kind {
+            NoiseKind::Perlin => self.perlin2(p),
+            NoiseKind::Simplex => self.simplex2(p),
+            NoiseKind::Worley => self.worley2(p),
+        }
+    }
+
+    pub fn sample3(&self, kind: NoiseKind, p: Vec3) -> f32 {
+        match kind {
+            NoiseKind::Perlin => self.perlin3(p),
+            NoiseKind::Simplex => self.simplex3(p),
+            NoiseKind::Worley => self.worley3(p),
+        }
+    }
+
+
+    fn hash2(&self, x: i32, y: i32) -> usize {
+        self.perm[(x & 255) as usize + self.perm[(y & 255) as usize]]
+    }
+
+    fn hash3(&self, x: i32, y: i32, z: i32) -> usize {
+        self.perm[(x & 255) as usize + self.perm[(y & 255) as usize + self.perm[(z & 255) as usize]]]
+    }
+
+
+    pub fn perlin2(&self, p: Vec2) -> f32 {
+        let cell = p.floor();
+        let (xi, yi) = (cell.x as i32, cell.y as i32);
+        let f = p - cell;
+        let u = fade(f.x);
+        let v = fade(f.y);
+
+        let n00 = GRAD2[self.hash2(xi, yi) & 7].dot(f);
+        let n10 = GRAD2[self.hash2(xi + 1, yi) & 7].dot(f - Vec2::new(1.0, 0.0));
+        let n01 = GRAD2[self.hash2(xi, yi + 1) & 7].dot(f - Vec2::new(0.0, 1.0));
+        let n11 = GRAD2[self.hash2(xi + 1, yi + 1) & 7].dot(f - Vec2::new(1.0, 1.0));
+
+        // The largest possible value of 2D gradient noise with unit gradients is sqrt(2) / 2
+        lerp(lerp(n00, n10, u), lerp(n01, n11, u), v) * std::f32::consts::SQRT_2
+    }
+
+    pub fn perlin3(&self, p: Vec3) -> f32 {
+        let cell = p.floor();
+        let (xi, yi, zi) = (cell.x as i32, cell.y as i32, cell.z as i32);
+        let f = p - cell;
+        let u = fade(f.x);
+        let v = fade(f.y);
+        let w = fade(f.z);
+
+        let grad = |dx: i32, dy: i32, dz: i32| {
+            let g = GRAD3[self.hash3(xi + dx, yi + dy, zi + dz) % 12];
+            g.dot(f - Vec3::new(dx as f32, dy as f32, dz as f32))
+        };
+
+        let x00 = lerp(grad(0, 0, 0), grad(1, 0, 0), u);
+        let x10 = lerp(grad(0, 1, 0), grad(1, 1, 0), u);
+        let x01 = lerp(grad(0, 0, 1), grad(1, 0, 1), u);
+        let x11 = lerp(grad(0, 1, 1), grad(1, 1, 1), u);
+        // The largest possible value with these gradients is about 1.0363, found numerically by
+        // picking the best gradient for every corner. Scaled like `perlin2` to reach 1 at most.
+        lerp(lerp(x00, x10, v), lerp(x01, x11, v), w) / 1.0363167
+    }
+
+
+    pub fn simplex2(&self, p: Vec2) -> f32 {
+        let s = (p.x + p.y) * F2;
+        let i = (p.x + s).floor();
+        let j = (p.y + s).floor();
+        let t = (i + j) * G2;
+        let p0 = p - Vec2::new(i - t, j - t);
+
+        // Which of the two triangles of the skewed cell we are in
+        let o1 = match p0.x > p0.y {
+            true => Vec2::new(1.0, 0.0),
+            false => Vec2::new(0.0, 1.0),
+        };
+        let p1 = p0 - o1 + G2;
+        let p2 = p0 - 1.0 + 2.0 * G2;
+
+        let (ii, jj) = (i as i32, j as i32);
+        let g0 = self.hash2(ii, jj) & 7;
+        let g1 = self.hash2(ii + o1.x as i32, jj + o1.y as i32) & 7;
+        let g2 = self.hash2(ii + 1, jj + 1) & 7;
+
+        let corner = |d: Vec2, g: usize| {
+            let t = 0.5 - d.length_squared();
+            match t < 0.0 {
+                true => 0.0,
+                false => t.powi(4) * GRAD2[g].dot(d),
+            }
+        };
+
+        70.0 * (corner(p0, g0) + corner(p1, g1) + corner(p2, g2))
+    }
+
+    pub fn simplex3(&self, p: Vec3) -> f32 {
+        let s = (p.x + p.y + p.z) * F3;
+        let cell = (p + s).floor();
+        let t = (cell.x + cell.y + cell.z) * G3;
+        let p0 = p - (cell - t);
+
+        // Pick the simplex (one of six tetrahedra) by sorting the offsets
+        let (o1, o2) = if p0.x >= p0.y {
+            if p0.y >= p0.z {
+                (Vec3::X, Vec3::new(1.0, 1.0, 0.0))
+            } else if p0.x >= p0.z {
+                (Vec3::X, Vec3::new(1.0, 0.0, 1.0))
+            } else {
+                (Vec3::Z, Vec3::new(1.0, 0.0, 1.0))
+            }
+        } else if p0.y < p0.z {
+            (Vec3::Z, Vec3::new(0.0, 1.0, 1.0))
+        } else if p0.x < p0.z {
+            (Vec3::Y, Vec3::new(0.0, 1.0, 1.0))
+        } else {
+            (Vec3::Y, Vec3::new(1.0, 1.0, 0.0))
+        };
+
+        let p1 = p0 - o1 + G3;
+        let p2 = p0 - o2 + 2.0 * G3;
+        let p3 = p0 - 1.0 + 3.0 * G3;
+
+        let (ii, jj, kk) = (cell.x as i32, cell.y as i32, cell.z as i32);
+        let grad = |o: Vec3| self.hash3(ii + o.x as i32, jj + o.y as i32, kk + o.z as i32) % 12;
+
+        let corner = |d: Vec3, g: usize| {
+            let t = 0.6 - d.length_squared();
+            match t < 0.0 {
+                true => 0.0,
+                false => t.powi(4) * GRAD3[g].dot(d),
+            }
+        };
+
+        32.0 * (
+            corner(p0, grad(Vec3::ZERO))
+            + corner(p1, grad(o1))
+            + corner(p2, grad(o2))
+            + corner(p3, grad(Vec3::ONE))
+        )
+    }
+
+
+    /// Distance to the nearest feature point, one jittered point per unit cell.
+    pub fn worley2(&self, p: Vec2) -> f32 {
+        let cell = p.floor();
+        let (xi, yi) = (cell.x as i32, cell.y as i32);
+        let mut min_dist = f32::MAX;
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let h = self.hash2(xi + dx, yi + dy);
+                let jitter = Vec2::new(
+                    self.perm[h] as f32 / 255.0,
+                    self.perm[h + 1] as f32 / 255.0,
+                );
+                let feature = Vec2::new((xi + dx) as f32, (yi + dy) as f32) + jitter;
+                min_dist = min_dist.min(feature.distance(p));
+            };
+        };
+        min_dist
+    }
+
+    pub fn worley3(&self, p: Vec3) -> f32 {
+        let cell = p.floor();
+        let (xi, yi, zi) = (cell.x as i32, cell.y as i32, cell.z as i32);
+        let mut min_dist = f32::MAX;
+        for dz in -1..=1 {
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    let h = self.hash3(xi + dx, yi + dy, zi + dz);
+                    let jitter = Vec3::new(
+                        self.perm[h] as f32 / 255.0,
+                        self.perm[h + 1] as f32 / 255.0,
+                        self.perm[h + 2] as f32 / 255.0,
+                    );
+                    let feature = Vec3::new((xi + dx) as f32, (yi + dy) as f32, (zi + dz) as f32) + jitter;
+                    min_dist = min_dist.min(feature.distance(p));
+                };
+            };
+        };
+        min_dist
+    }
+}
+
+
+/// Sums several octaves of a base noise, each at a higher frequency and lower amplitude.
+#[derive(Clone, Copy, Debug)]
+pub struct Fractal {
+    pub kind: NoiseKind,
+    pub octaves: u32,
+    pub frequency: f32,
+    pub lacunarity: f32,
+    pub gain: f32,
+}
+
+impl Default for Fractal {
+    fn default() -> Self {
+        Fractal {
+            kind: NoiseKind::Perlin,
+            octaves: 5,
+            frequency: 1.0,
+            lacunarity: 2.0,
+            gain: 0.5,
+        }
+    }
+}
+
+impl Fractal {
+    pub fn new(kind: NoiseKind, octaves: u32) -> Self {
+        Fractal {
+            kind,
+            octaves,
+            ..Default::default()
+        }
+    }
+
+    /// Fractional Brownian motion, normalized back into the range of the base noise.
+    pub fn fbm2(&self, noise: &Noise, p: Vec2) -> f32 {
+        self.accumulate(|freq| noise.sample2(self.kind, p * freq))
+    }
+
+    pub fn fbm3(&self, noise: &Noise, p: Vec3) -> f32 {
+        self.accumulate(|freq| noise.sample3(self.kind, p * freq))
+    }
+
+    /// Sum of absolute values, giving billowy, always positive output.
+    pub fn turbulence2(&self, noise: &Noise, p: Vec2) -> f32 {
+        self.accumulate(|freq| noise.sample2(self.kind, p * freq).abs())
+    }
+
+    pub fn turbulence3(&self, noise: &Noise, p: Vec3) -> f32 {
+        self.accumulate(|freq| noise.sample3(self.kind, p * freq).abs())
+    }
+
+    /// Inverted turbulence, producing sharp ridges in `[0, 1]`. Good for mountain ranges.
+    pub fn ridged2(&self, noise: &Noise, p: Vec2) -> f32 {
+        self.accumulate(|freq| ridge(noise.sample2(self.kind, p * freq)))
+    }
+
+    pub fn ridged3(&self, noise: &Noise, p: Vec3) -> f32 {
+        self.accumulate(|freq| ridge(noise.sample3(self.kind, p * freq)))
+    }
+
+    fn accumulate<F: Fn(f32) -> f32>(&self, octave: F) -> f32 {
+        let mut sum = 0.0;
+        let mut norm = 0.0;
+        let mut freq = self.frequency;
+        let mut amp = 1.0;
+        for _ in 0..self.octaves {
+            sum += octave(freq) * amp;
+            norm += amp;
+            freq *= self.lacunarity;
+            amp *= self.gain;
+        };
+        match norm > 0.0 {
+            true => sum / norm,
+            false => 0.0,
+        }
+    }
+}
+
+
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+fn ridge(n: f32) -> f32 {
+    let r = 1.0 - n.abs();
+    r * r
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid() -> impl Iterator<Item = Vec3> {
+        (0..2000).map(|i| Vec3::new(i as f32 * 0.137, (i % 37) as f32 * 0.291, (i % 11) as f32 * 0.173))
+    }
+
+    #[test]
+    fn same_seed_same_noise() {
+        let (a, b, c) = (Noise::new(9), Noise::new(9), Noise::new(10));
+        for kind in [NoiseKind::Perlin, NoiseKind::Simplex, NoiseKind::Worley] {
+            for p in grid().take(200) {
+                assert_eq!(a.sample3(kind, p), b.sample3(kind, p));
+                assert_eq!(a.sample2(kind, p.truncate()), b.sample2(kind, p.truncate()));
+            };
+            assert!(grid().take(200).any(|p| a.sample3(kind, p) != c.sample3(kind, p)));
+        };
+    }
+
+    #[test]
+    fn perlin_in_unit_range() {
+        let noise = Noise::new(3);
+        let (mut max2, mut max3) = (0.0f32, 0.0f32);
+        for p in grid() {
+            max2 = max2.max(noise.perlin2(p.truncate()).abs());
+            max3 = max3.max(noise.perlin3(p).abs());
+        };
+        assert!(max2 <= 1.0 && max3 <= 1.0);
+        // And both reach well past 0.6, so neither scale factor squashes its noise into a narrower band
+        assert!(max2 > 0.6 && max3 > 0.6);
+    }
+
+    #[test]
+    fn perlin_zero_on_lattice() {
+        let noise = Noise::new(5);
+        assert_eq!(noise.perlin2(Vec2::new(3.0, -2.0)), 0.0);
+        assert_eq!(noise.perlin3(Vec3::new(3.0, -2.0, 7.0)), 0.0);
+    }
+}
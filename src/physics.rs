@@ -0,0 +1,297 @@
+use glam::Vec3;
+
+use crate::{aabb::Aabb, collision, drawable::Drawable};
+
+
+/// Normal speeds below this do not bounce, so resting bodies settle instead of jittering.
+/// Raised to a few steps worth of gravity when that is larger.
+const BOUNCE_THRESHOLD: f32 = 5.0;
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RigidBody {
+    /// Index of the object this body moves
+    pub object: usize,
+    pub velocity: Vec3,
+    pub mass: f32,
+    /// Fraction of the normal speed kept when bouncing off something, `0..=1`
+    pub restitution: f32,
+    /// Coulomb friction coefficient against whatever the body touches
+    pub friction: f32,
+    /// Whether the body ended the last step resting on something
+    pub grounded: bool,
+}
+
+impl RigidBody {
+    pub fn new(object: usize, mass: f32) -> Self {
+        RigidBody {
+            object,
+            velocity: Vec3::ZERO,
+            mass,
+            restitution: 0.2,
+            friction: 0.5,
+            grounded: false,
+        }
+    }
+
+    fn inv_mass(&self) -> f32 {
+        match self.mass > 0.0 {
+            true => 1.0 / self.mass,
+            false => 0.0,
+        }
+    }
+}
+
+
+/// Moves dynamic bodies under gravity. Every object with a collider that has no body is static.
+#[derive(Clone, Debug)]
+pub struct Physics {
+    /// Positive y points down in world space
+    pub gravity: Vec3,
+    pub bodies: Vec<RigidBody>,
+}
+
+impl Default for Physics {
+    fn default() -> Self {
+        Physics::new()
+    }
+}
+
+impl Physics {
+    pub fn new() -> Self {
+        Physics {
+            gravity: Vec3::new(0.0, 160.0, 0.0),
+            bodies: vec![],
+        }
+    }
+
+    pub fn add_body(&mut self, body: RigidBody) -> usize {
+        self.bodies.push(body);
+        self.bodies.len() - 1
+    }
+
+    pub fn body_for(&self, object: usize) -> Option<&RigidBody> {
+        self.bodies.iter().find(|body| body.object == object)
+    }
+
+    pub fn body_for_mut(&mut self, object: usize) -> Option<&mut RigidBody> {
+        self.bodies.iter_mut().find(|body| body.object == object)
+    }
+
+    pub fn step(&mut self, dt: f32, objects: &mut [Box<dyn Drawable>]) {
+        let mut colliders: Vec<Option<Aabb>> = objects.iter().map(|object| object.get_collider()).collect();
+        let mut body_of = vec![None; objects.len()];
+        for (idx, body) in self.bodies.iter().enumerate() {
+            if let Some(slot) = body_of.get_mut(body.object) {
+                *slot = Some(idx);
+            };
+        };
+
+        let bounce_threshold = BOUNCE_THRESHOLD.max(2.0 * self.gravity.length() * dt);
+
+        for idx in 0..self.bodies.len() {
+            let object = self.bodies[idx].object;
+            let start = match colliders.get(object) {
+                Some(Some(aabb)) => *aabb,
+                _ => continue,
+            };
+
+            // Get out of anything we start inside of, e.g. after being spawned in a wall
+            let others: Vec<Aabb> = colliders.iter()
+                .enumerate()
+                .filter(|(other, _)| *other != object)
+                .filter_map(|(_, aabb)| *aabb)
+                .collect();
+            let mut current = start.translated(collision::resolve_overlaps(&start, &others));
+
+            self.bodies[idx].velocity += self.gravity * dt;
+            self.bodies[idx].grounded = false;
+            let mut delta = self.bodies[idx].velocity * dt;
+
+            for _ in 0..3 {
+                if delta.length_squared() < 1e-8 {
+                    break;
+                };
+                let earliest = colliders.iter()
+                    .enumerate()
+                    .filter(|(other, _)| *other != object)
+                    .filter_map(|(other, aabb)| aabb.and_then(|aabb| collision::sweep(&current, delta, &aabb)).map(|hit| (other, hit)))
+                    .min_by(|a, b| a.1.time.partial_cmp(&b.1.time).unwrap_or(std::cmp::Ordering::Equal));
+
+                let (other, hit) = match earliest {
+                    Some(earliest) => earliest,
+                    None => {
+                        current = current.translated(delta);
+                        break;
+                    },
+                };
+                current = current.translated(delta * hit.time);
+
+                match body_of[other] {
+                    Some(other_idx) => self.collide_bodies(idx, other_idx, hit.normal, bounce_threshold),
+                    None => self.collide_static(idx, hit.normal, bounce_threshold),
+                };
+                if hit.normal.dot(-self.gravity) > 0.0 {
+                    self.bodies[idx].grounded = true;
+                };
+
+                let remaining = delta * (1.0 - hit.time);
+                delta = remaining - hit.normal * remaining.dot(hit.normal);
+            };
+
+            colliders[object] = Some(current);
+            let offset = current.center() - start.center();
+            if offset != Vec3::ZERO {
+                let origin = objects[object].get_origin();
+                objects[object].set_origin(origin + offset);
+            };
+        };
+    }
+
+    fn collide_static(&mut self, idx: usize, normal: Vec3, bounce_threshold: f32) {
+        let body = &mut self.bodies[idx];
+        let normal_speed = body.velocity.dot(normal);
+        if normal_speed >= 0.0 {
+            return;
+        };
+        let bounce = match -normal_speed > bounce_threshold {
+            true => body.restitution,
+            false => 0.0,
+        };
+        let impulse = -normal_speed * (1.0 + bounce);
+        body.velocity += normal * impulse;
+        body.velocity = apply_friction(body.velocity, normal, impulse * body.friction);
+    }
+
+    fn collide_bodies(&mut self, a: usize, b: usize, normal: Vec3, bounce_threshold: f32) {
+        let (body_a, body_b) = (self.bodies[a], self.bodies[b]);
+        let relative_speed = (body_a.velocity - body_b.velocity).dot(normal);
+        if relative_speed >= 0.0 {
+            return;
+        };
+        let inv_mass_sum = body_a.inv_mass() + body_b.inv_mass();
+        if inv_mass_sum <= 0.0 {
+            return;
+        };
+        let restitution = match -relative_speed > bounce_threshold {
+            true => body_a.restitution.min(body_b.restitution),
+            false => 0.0,
+        };
+        let friction = (body_a.friction * body_b.friction).sqrt();
+        let impulse = -relative_speed * (1.0 + restitution) / inv_mass_sum;
+
+        let dv_a = impulse * body_a.inv_mass();
+        let dv_b = impulse * body_b.inv_mass();
+        self.bodies[a].velocity = apply_friction(body_a.velocity + normal * dv_a, normal, dv_a * friction);
+        self.bodies[b].velocity = apply_friction(body_b.velocity - normal * dv_b, normal, dv_b * friction);
+    }
+}
+
+
+/// Slows the velocity along the contact surface by up to `amount`, without reversing it.
+fn apply_friction(velocity: Vec3, normal: Vec3, amount: f32) -> Vec3 {
+    let tangent = velocity - normal * velocity.dot(normal);
+    let speed = tangent.length();
+    if speed <= amount || speed == 0.0 {
+        return velocity - tangent;
+    };
+    velocity - tangent * (amount / speed)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{boxshape::BoxShape, color::Color};
+
+    const DT: f32 = 1.0 / 25.0;
+
+    /// A floor with its top at y 0, as object 0, and a crate of size 4 centered at `pos`, as object 1.
+    fn world(pos: Vec3) -> Vec<Box<dyn Drawable>> {
+        let color = Color::rgb(1.0, 1.0, 1.0);
+        vec![
+            Box::new(BoxShape::new(Vec3::new(0.0, 1.0, 0.0), Vec3::new(80.0, 2.0, 80.0), color)),
+            Box::new(BoxShape::new(pos, Vec3::splat(4.0), color)),
+        ]
+    }
+
+    fn bottom(objects: &[Box<dyn Drawable>], object: usize) -> f32 {
+        objects[object].get_collider().unwrap().max.y
+    }
+
+    #[test]
+    fn falls_and_comes_to_rest() {
+        let mut objects = world(Vec3::new(0.0, -40.0, 0.0));
+        let mut physics = Physics::new();
+        physics.add_body(RigidBody::new(1, 1.0));
+
+        physics.step(DT, &mut objects);
+        assert!(bottom(&objects, 1) > -38.0);
+        assert!(physics.bodies[0].velocity.y > 0.0);
+        for _ in 0..100 {
+            physics.step(DT, &mut objects);
+        };
+        assert!(bottom(&objects, 1).abs() < 1e-3, "{}", bottom(&objects, 1));
+        assert!(physics.bodies[0].grounded);
+        // Resting, apart from the gravity of the step that is about to be cancelled again
+        let before = objects[1].get_origin();
+        physics.step(DT, &mut objects);
+        assert!(objects[1].get_origin().abs_diff_eq(before, 1e-3));
+        assert_eq!(physics.bodies[0].velocity, Vec3::ZERO);
+    }
+
+    #[test]
+    fn bounces_above_threshold_only() {
+        // No gravity, so the speeds stay exact and the threshold is `BOUNCE_THRESHOLD`
+        for (speed, expected) in [(50.0, -25.0), (BOUNCE_THRESHOLD - 1.0, 0.0)] {
+            // Close enough to the floor to touch it within the step, even at the lower speed
+            let mut objects = world(Vec3::new(0.0, -2.1, 0.0));
+            let mut physics = Physics::new();
+            physics.gravity = Vec3::ZERO;
+            physics.add_body(RigidBody {velocity: Vec3::new(0.0, speed, 0.0), restitution: 0.5, ..RigidBody::new(1, 1.0)});
+            physics.step(DT, &mut objects);
+            assert_eq!(physics.bodies[0].velocity.y, expected, "{}", speed);
+            assert!(bottom(&objects, 1) <= 1e-4);
+        };
+    }
+
+    #[test]
+    fn friction_stops_sliding() {
+        let slide = |friction: f32| {
+            let mut objects = world(Vec3::new(0.0, -2.0, 0.0));
+            let mut physics = Physics::new();
+            physics.add_body(RigidBody {velocity: Vec3::new(20.0, 0.0, 0.0), friction, ..RigidBody::new(1, 1.0)});
+            for _ in 0..25 {
+                physics.step(DT, &mut objects);
+            };
+            (physics.bodies[0].velocity.x, objects[1].get_origin().x)
+        };
+        let (speed, distance) = slide(0.5);
+        assert_eq!(speed, 0.0);
+        assert!(distance > 0.0 && distance < 10.0, "{}", distance);
+        // Without friction it keeps going
+        let (speed, distance) = slide(0.0);
+        assert_eq!(speed, 20.0);
+        assert!(distance > 19.0, "{}", distance);
+    }
+
+    #[test]
+    fn pushes_crate_along_floor() {
+        let mut objects = world(Vec3::new(0.0, -2.0, 0.0));
+        objects.push(Box::new(BoxShape::new(Vec3::new(6.0, -2.0, 0.0), Vec3::splat(4.0), Color::rgb(1.0, 0.0, 0.0))));
+        let mut physics = Physics::new();
+        physics.add_body(RigidBody::new(1, 1.0));
+        physics.add_body(RigidBody::new(2, 1.0));
+        for _ in 0..25 {
+            // Walking, like the player does by setting its speed every tick
+            physics.bodies[0].velocity.x = 30.0;
+            physics.step(DT, &mut objects);
+        };
+        let (pusher, pushed) = (objects[1].get_collider().unwrap(), objects[2].get_collider().unwrap());
+        assert!(pushed.center().x > 15.0, "{}", pushed.center().x);
+        assert!(pushed.min.x >= pusher.max.x - 1e-3);
+        assert!(!collision::overlaps(&pusher, &pushed));
+        // Both stayed on the floor
+        assert!(bottom(&objects, 1).abs() < 1e-3 && bottom(&objects, 2).abs() < 1e-3);
+    }
+}
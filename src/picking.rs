@@ -0,0 +1,122 @@
+use glam::{Vec2, Vec3, Mat3};
+
+use crate::{aabb::Aabb, bvh::Bvh, ray::Ray};
+
+
+/// Value in an object id buffer for pixels no object was drawn to.
+pub const NO_OBJECT: u32 = u32::MAX;
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PickHit {
+    /// Whatever the index stores per object, usually the position in `World::objects`
+    pub object: usize,
+    /// World space point where the ray enters the object
+    pub point: Vec3,
+    pub distance: f32,
+}
+
+
+/// Ray through screen position `screen` for an orthographic camera with view transform
+/// `rotation * p - camera_pos`, starting in front of everything in `scene_bounds`.
+///
+/// Points with a larger view space z are drawn on top, so the ray travels towards smaller z.
+pub fn ortho_screen_ray(rotation: Mat3, camera_pos: Vec3, screen: Vec2, scene_bounds: &Aabb) -> Ray {
+    let mut start_z = 0.0;
+    if !scene_bounds.is_empty() {
+        start_z = scene_bounds.corners()
+            .iter()
+            .map(|corner| (rotation * *corner - camera_pos).z)
+            .fold(f32::MIN, f32::max) + 1.0;
+    };
+    let view_origin = Vec3::new(screen.x, screen.y, start_z);
+    // The view rotation is orthonormal, so its transpose is its inverse
+    let inv_rotation = rotation.transpose();
+    Ray::new(
+        inv_rotation * (view_origin + camera_pos),
+        inv_rotation * Vec3::new(0.0, 0.0, -1.0),
+    )
+}
+
+/// Closest object in `index` hit by `ray`, tested against the object bounds.
+pub fn pick(index: &Bvh<usize>, ray: &Ray) -> Option<PickHit> {
+    index.raycast(ray, f32::MAX, |_, t| Some(t))
+        .map(|(object, distance)| PickHit {
+            object,
+            point: ray.at(distance),
+            distance,
+        })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{boxshape::BoxShape, camera::Camera, color::Color, drawable::Drawable, raster::{self, Batch, Positions, View}};
+
+    const WIDTH: usize = 120;
+    const HEIGHT: usize = 80;
+
+    /// A box standing on a floor, indexed as objects 0 and 1, seen from above at an angle.
+    fn scene() -> (Vec<BoxShape>, Bvh<usize>, Camera, View) {
+        let objects = vec![
+            BoxShape::new(Vec3::new(10.0, -5.0, 4.0), Vec3::new(8.0, 6.0, 10.0), Color::rgb(1.0, 0.0, 0.0)),
+            BoxShape::new(Vec3::new(0.0, 1.0, 0.0), Vec3::new(60.0, 2.0, 60.0), Color::rgb(0.5, 0.5, 0.5)),
+        ];
+        let mut index = Bvh::new();
+        for (idx, object) in objects.iter().enumerate() {
+            index.insert(object.get_bounds(), idx);
+        };
+        let camera = Camera {target: Vec3::new(5.0, 0.0, 0.0), yaw: 0.3, pitch: 1.2, zoom: 1.5};
+        let view = View::new(&camera, WIDTH, HEIGHT, Vec3::new(0.0, -1.0, 0.0), 1.0);
+        (objects, index, camera, view)
+    }
+
+    fn ray(camera: &Camera, view: &View, index: &Bvh<usize>, screen: Vec2) -> Ray {
+        camera.screen_ray(screen, view.center.truncate(), &index.root_bounds().unwrap())
+    }
+
+    #[test]
+    fn hits_top_of_box() {
+        let (_, index, camera, view) = scene();
+        // Middle of the top face, which is the smallest y
+        let top = Vec3::new(10.0, -8.0, 4.0);
+        let screen = view.to_screen(top).truncate();
+        let hit = pick(&index, &ray(&camera, &view, &index, screen)).unwrap();
+        assert_eq!(hit.object, 0);
+        assert!(hit.point.abs_diff_eq(top, 1e-3), "{}", hit.point);
+
+        // Next to the box the floor is hit, on its top at y 0
+        let floor = Vec3::new(-10.0, 0.0, -10.0);
+        let hit = pick(&index, &ray(&camera, &view, &index, view.to_screen(floor).truncate())).unwrap();
+        assert_eq!(hit.object, 1);
+        assert!(hit.point.abs_diff_eq(floor, 1e-3), "{}", hit.point);
+    }
+
+    #[test]
+    fn agrees_with_id_buffer() {
+        let (objects, index, camera, view) = scene();
+        let positions: Vec<Positions> = objects.iter().map(|object| Positions::from_surfels(object.get_points())).collect();
+        let batches: Vec<Batch> = objects.iter().zip(positions.iter()).enumerate()
+            .map(|(idx, (object, positions))| Batch {
+                points: object.get_points(),
+                positions,
+                revision: object.revision(),
+                offset: Vec3::ZERO,
+                object_id: idx as u32,
+            })
+            .collect();
+        let mut frame = vec![0u8; WIDTH * HEIGHT * 4];
+        let mut ids = vec![0u32; WIDTH * HEIGHT];
+        raster::draw(&view, &batches, 1, &mut frame, Some(&mut ids));
+
+        let (x, y) = view.pixel(view.to_screen(Vec3::new(10.0, -8.0, 4.0))).unwrap();
+        assert_eq!(ids[x + y * WIDTH], 0);
+        let hit = pick(&index, &ray(&camera, &view, &index, Vec2::new(x as f32, y as f32))).unwrap();
+        assert_eq!(hit.object, 0);
+
+        // The corner of the screen is past the edge of the floor
+        assert_eq!(ids[0], NO_OBJECT);
+        assert_eq!(pick(&index, &ray(&camera, &view, &index, Vec2::ZERO)), None);
+    }
+}
@@ -0,0 +1,122 @@
+use std::f32::consts::FRAC_PI_4;
+
+use glam::{Vec2, Vec3};
+
+use crate::{camera::Camera, input::Actions, physics::{Physics, RigidBody}};
+
+
+/// Character steered with the `move_x`/`move_y` axes, in one of 8 directions relative to the camera.
+///
+/// Movement goes through the player's `RigidBody`, so it falls and collides like everything else.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Player {
+    /// Index of the object this player moves
+    pub object: usize,
+    /// World units per second
+    pub max_speed: f32,
+    /// Speed gained per second while moving
+    pub acceleration: f32,
+    /// Speed lost per second after letting go
+    pub deceleration: f32,
+    /// Last direction the player moved in, on the ground plane
+    pub facing: Vec3,
+}
+
+impl Player {
+    pub fn new(object: usize) -> Self {
+        Player {
+            object,
+            max_speed: 60.0,
+            acceleration: 400.0,
+            deceleration: 600.0,
+            facing: Vec3::Z,
+        }
+    }
+
+    /// Body to add to `Physics` for this player. It neither bounces nor slows down on its own,
+    /// as the controller takes care of that.
+    pub fn body(&self, mass: f32) -> RigidBody {
+        RigidBody {
+            restitution: 0.0,
+            friction: 0.0,
+            ..RigidBody::new(self.object, mass)
+        }
+    }
+
+    /// Snaps `input` to the closest of 8 directions, keeping its length up to 1.
+    pub fn eight_way(input: Vec2) -> Vec2 {
+        let length = input.length().min(1.0);
+        if length == 0.0 {
+            return Vec2::ZERO;
+        };
+        let angle = (input.y.atan2(input.x) / FRAC_PI_4).round() * FRAC_PI_4;
+        Vec2::new(angle.cos(), angle.sin()) * length
+    }
+
+    /// Accelerates the player's body towards the requested direction. Call before `Physics::step`.
+    pub fn update(&mut self, actions: &Actions, camera: &Camera, physics: &mut Physics, dt: f32) {
+        let input = Player::eight_way(Vec2::new(actions.axis("move_x"), actions.axis("move_y")));
+        let wish = camera.ground_dir(input) * input.length() * self.max_speed;
+
+        let body = match physics.body_for_mut(self.object) {
+            Some(body) => body,
+            None => return,
+        };
+        // Only steer along the ground, gravity handles the rest
+        let vertical = Vec3::new(0.0, body.velocity.y, 0.0);
+        let horizontal = body.velocity - vertical;
+
+        let rate = match wish == Vec3::ZERO {
+            true => self.deceleration,
+            false => self.acceleration,
+        };
+        let diff = wish - horizontal;
+        let step = rate * dt;
+        let horizontal = match diff.length() <= step {
+            true => wish,
+            false => horizontal + diff.normalize() * step,
+        };
+        body.velocity = horizontal + vertical;
+
+        if wish != Vec3::ZERO {
+            self.facing = wish.normalize();
+        };
+    }
+}
+
+
+/// Keeps the camera on a target, only moving once the target leaves a dead zone around the center.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FollowCamera {
+    /// Half size of the dead zone in world units, across and along the view on the ground
+    pub dead_zone: Vec2,
+    /// How quickly the camera catches up, higher is snappier
+    pub stiffness: f32,
+}
+
+impl Default for FollowCamera {
+    fn default() -> Self {
+        FollowCamera {
+            dead_zone: Vec2::new(20.0, 12.0),
+            stiffness: 6.0,
+        }
+    }
+}
+
+impl FollowCamera {
+    pub fn update(&self, camera: &mut Camera, target: Vec3, dt: f32) {
+        let right = camera.ground_dir(Vec2::X);
+        let down = camera.ground_dir(Vec2::Y);
+        let delta = target - camera.target;
+
+        let outside = |offset: f32, dead_zone: f32| offset.signum() * (offset.abs() - dead_zone).max(0.0);
+        let goal = camera.target
+            + right * outside(delta.dot(right), self.dead_zone.x)
+            + down * outside(delta.dot(down), self.dead_zone.y)
+            + Vec3::Y * delta.y;
+
+        // Frame rate independent exponential smoothing
+        let t = 1.0 - (-self.stiffness * dt).exp();
+        camera.target = camera.target.lerp(goal, t);
+    }
+}
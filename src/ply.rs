@@ -0,0 +1,382 @@
+use std::{fmt, fs::File, io::{self, BufRead, BufReader, BufWriter, Read, Write}, path::Path};
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
+use glam::Vec3;
+
+use crate::{color::Color, drawable::Drawable, pointcloud::PointCloud, surfel::Surfel};
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+impl PlyFormat {
+    fn name(&self) -> &'static str {
+        match self {
+            PlyFormat::Ascii => "ascii",
+            PlyFormat::BinaryLittleEndian => "binary_little_endian",
+            PlyFormat::BinaryBigEndian => "binary_big_endian",
+        }
+    }
+}
+
+
+#[derive(Debug)]
+pub enum PlyError {
+    Io(io::Error),
+    /// Something in the file doesn't follow the format, or uses parts of it we don't read
+    Invalid(String),
+}
+
+impl fmt::Display for PlyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlyError::Io(e) => write!(f, "{}", e),
+            PlyError::Invalid(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for PlyError {}
+
+impl From<io::Error> for PlyError {
+    fn from(e: io::Error) -> Self {
+        PlyError::Io(e)
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Option<Scalar> {
+        match name {
+            "char" | "int8" => Some(Scalar::I8),
+            "uchar" | "uint8" => Some(Scalar::U8),
+            "short" | "int16" => Some(Scalar::I16),
+            "ushort" | "uint16" => Some(Scalar::U16),
+            "int" | "int32" => Some(Scalar::I32),
+            "uint" | "uint32" => Some(Scalar::U32),
+            "float" | "float32" => Some(Scalar::F32),
+            "double" | "float64" => Some(Scalar::F64),
+            _ => None,
+        }
+    }
+
+    /// Largest value of integer types, used to bring colours into `0..=1`.
+    fn max(&self) -> f64 {
+        match self {
+            Scalar::I8 => i8::MAX as f64,
+            Scalar::U8 => u8::MAX as f64,
+            Scalar::I16 => i16::MAX as f64,
+            Scalar::U16 => u16::MAX as f64,
+            Scalar::I32 => i32::MAX as f64,
+            Scalar::U32 => u32::MAX as f64,
+            Scalar::F32 | Scalar::F64 => 1.0,
+        }
+    }
+
+    fn read<B: ByteOrder, R: Read>(&self, r: &mut R) -> io::Result<f64> {
+        Ok(match self {
+            Scalar::I8 => r.read_i8()? as f64,
+            Scalar::U8 => r.read_u8()? as f64,
+            Scalar::I16 => r.read_i16::<B>()? as f64,
+            Scalar::U16 => r.read_u16::<B>()? as f64,
+            Scalar::I32 => r.read_i32::<B>()? as f64,
+            Scalar::U32 => r.read_u32::<B>()? as f64,
+            Scalar::F32 => r.read_f32::<B>()? as f64,
+            Scalar::F64 => r.read_f64::<B>()?,
+        })
+    }
+}
+
+
+#[derive(Clone, Debug)]
+enum Property {
+    Scalar {name: String, ty: Scalar},
+    /// Lists are only skipped, e.g. the vertex indices of faces
+    List {count: Scalar, item: Scalar},
+}
+
+#[derive(Clone, Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+
+/// Reads the points of a PLY file, ignoring faces and anything else that isn't a vertex.
+/// Vertices need `x`, `y` and `z`; normals (`nx`, `ny`, `nz`) and colours
+/// (`red`, `green`, `blue`, `alpha`) are used when present.
+pub fn read<R: BufRead>(mut reader: R) -> Result<Vec<Surfel>, PlyError> {
+    let (format, elements) = read_header(&mut reader)?;
+    let mut points = vec![];
+    for element in elements.iter() {
+        let is_vertex = element.name == "vertex";
+        let layout = VertexLayout::new(&element.properties);
+        if is_vertex && layout.position.iter().any(Option::is_none) {
+            return Err(PlyError::Invalid("vertices have no x, y and z".to_string()));
+        };
+        if is_vertex {
+            points.reserve(element.count.min(1 << 20));
+        };
+
+        let mut values = vec![0.0; element.properties.len()];
+        let mut ascii_line = String::new();
+        for _ in 0..element.count {
+            match format {
+                PlyFormat::Ascii => read_ascii_row(&mut reader, &element.properties, &mut values, &mut ascii_line)?,
+                PlyFormat::BinaryLittleEndian => read_binary_row::<LittleEndian, _>(&mut reader, &element.properties, &mut values)?,
+                PlyFormat::BinaryBigEndian => read_binary_row::<BigEndian, _>(&mut reader, &element.properties, &mut values)?,
+            };
+            if is_vertex {
+                points.push(layout.surfel(&values));
+            };
+        };
+    };
+    Ok(points)
+}
+
+/// Loads a PLY file as a drawable, taking the coordinates in the file as world coordinates.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<PointCloud, PlyError> {
+    let points = read(BufReader::new(File::open(path)?))?;
+    Ok(PointCloud::new(Vec3::ZERO, points))
+}
+
+pub fn write<W: Write>(mut writer: W, points: &[Surfel], format: PlyFormat) -> Result<(), PlyError> {
+    writeln!(writer, "ply")?;
+    writeln!(writer, "format {} 1.0", format.name())?;
+    writeln!(writer, "comment written by topdown")?;
+    writeln!(writer, "element vertex {}", points.len())?;
+    for name in ["x", "y", "z", "nx", "ny", "nz"] {
+        writeln!(writer, "property float {}", name)?;
+    };
+    for name in ["red", "green", "blue", "alpha"] {
+        writeln!(writer, "property uchar {}", name)?;
+    };
+    writeln!(writer, "end_header")?;
+
+    for surfel in points {
+        let c = surfel.color.as_255();
+        let rgba = [c.r, c.g, c.b, c.a].map(|channel| channel.round().clamp(0.0, 255.0) as u8);
+        let (p, n) = (surfel.pos, surfel.normal);
+        match format {
+            PlyFormat::Ascii => writeln!(
+                writer, "{} {} {} {} {} {} {} {} {} {}",
+                p.x, p.y, p.z, n.x, n.y, n.z, rgba[0], rgba[1], rgba[2], rgba[3],
+            )?,
+            PlyFormat::BinaryLittleEndian => write_binary_row::<LittleEndian, _>(&mut writer, p, n, rgba)?,
+            PlyFormat::BinaryBigEndian => write_binary_row::<BigEndian, _>(&mut writer, p, n, rgba)?,
+        };
+    };
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes the points of all `objects` into one file, e.g. a whole world.
+pub fn save<P: AsRef<Path>>(path: P, objects: &[&dyn Drawable], format: PlyFormat) -> Result<(), PlyError> {
+    let points: Vec<Surfel> = objects.iter().flat_map(|object| object.get_points().iter().copied()).collect();
+    write(BufWriter::new(File::create(path)?), &points, format)
+}
+
+
+fn read_header<R: BufRead>(reader: &mut R) -> Result<(PlyFormat, Vec<Element>), PlyError> {
+    let invalid = |message: String| PlyError::Invalid(message);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if line.trim_end() != "ply" {
+        return Err(invalid("not a PLY file".to_string()));
+    };
+
+    let mut format = None;
+    let mut elements: Vec<Element> = vec![];
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid("the header has no end_header".to_string()));
+        };
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["end_header"] => break,
+            ["comment", ..] | ["obj_info", ..] | [] => {},
+            ["format", name, _version] => {
+                format = Some(match *name {
+                    "ascii" => PlyFormat::Ascii,
+                    "binary_little_endian" => PlyFormat::BinaryLittleEndian,
+                    "binary_big_endian" => PlyFormat::BinaryBigEndian,
+                    _ => return Err(invalid(format!("unknown format {}", name))),
+                });
+            },
+            ["element", name, count] => {
+                let count = count.parse().map_err(|_| invalid(format!("bad element count {}", count)))?;
+                elements.push(Element {name: name.to_string(), count, properties: vec![]});
+            },
+            ["property", "list", count, item, _name] => {
+                let property = match (Scalar::parse(count), Scalar::parse(item)) {
+                    (Some(count), Some(item)) => Property::List {count, item},
+                    _ => return Err(invalid(format!("unknown type in '{}'", line.trim()))),
+                };
+                elements.last_mut()
+                    .ok_or_else(|| invalid("property before any element".to_string()))?
+                    .properties.push(property);
+            },
+            ["property", ty, name] => {
+                let ty = Scalar::parse(ty).ok_or_else(|| invalid(format!("unknown type {}", ty)))?;
+                elements.last_mut()
+                    .ok_or_else(|| invalid("property before any element".to_string()))?
+                    .properties.push(Property::Scalar {name: name.to_string(), ty});
+            },
+            _ => return Err(invalid(format!("unexpected header line '{}'", line.trim()))),
+        };
+    };
+    match format {
+        Some(format) => Ok((format, elements)),
+        None => Err(invalid("the header has no format".to_string())),
+    }
+}
+
+/// Fills `values` with the scalar properties of one row. List properties are skipped and leave 0.
+fn read_ascii_row<R: BufRead>(reader: &mut R, properties: &[Property], values: &mut [f64], line: &mut String) -> Result<(), PlyError> {
+    line.clear();
+    if reader.read_line(line)? == 0 {
+        return Err(PlyError::Io(io::ErrorKind::UnexpectedEof.into()));
+    };
+    let mut words = line.split_whitespace();
+    let mut next = || -> Result<f64, PlyError> {
+        let word = words.next().ok_or_else(|| PlyError::Invalid("row is missing values".to_string()))?;
+        word.parse().map_err(|_| PlyError::Invalid(format!("bad number {}", word)))
+    };
+    for (property, value) in properties.iter().zip(values.iter_mut()) {
+        match property {
+            Property::Scalar {..} => *value = next()?,
+            Property::List {..} => {
+                let count = next()? as usize;
+                for _ in 0..count {
+                    next()?;
+                };
+            },
+        };
+    };
+    Ok(())
+}
+
+fn read_binary_row<B: ByteOrder, R: Read>(reader: &mut R, properties: &[Property], values: &mut [f64]) -> Result<(), PlyError> {
+    for (property, value) in properties.iter().zip(values.iter_mut()) {
+        match property {
+            Property::Scalar {ty, ..} => *value = ty.read::<B, _>(reader)?,
+            Property::List {count, item} => {
+                let count = count.read::<B, _>(reader)? as usize;
+                for _ in 0..count {
+                    item.read::<B, _>(reader)?;
+                };
+            },
+        };
+    };
+    Ok(())
+}
+
+fn write_binary_row<B: ByteOrder, W: Write>(writer: &mut W, p: Vec3, n: Vec3, rgba: [u8; 4]) -> io::Result<()> {
+    for v in [p.x, p.y, p.z, n.x, n.y, n.z] {
+        writer.write_f32::<B>(v)?;
+    };
+    writer.write_all(&rgba)
+}
+
+
+/// Where the properties we care about are in a vertex row.
+struct VertexLayout {
+    position: [Option<usize>; 3],
+    normal: [Option<usize>; 3],
+    /// Index and scale into `0..=1` of red, green, blue and alpha
+    color: [Option<(usize, f64)>; 4],
+}
+
+impl VertexLayout {
+    fn new(properties: &[Property]) -> Self {
+        let find = |names: &[&str]| properties.iter().position(|property| match property {
+            Property::Scalar {name, ..} => names.contains(&name.as_str()),
+            Property::List {..} => false,
+        });
+        let find_color = |names: &[&str]| find(names).map(|idx| match &properties[idx] {
+            Property::Scalar {ty, ..} => (idx, 1.0 / ty.max()),
+            Property::List {..} => (idx, 1.0),
+        });
+        VertexLayout {
+            position: [find(&["x"]), find(&["y"]), find(&["z"])],
+            normal: [find(&["nx"]), find(&["ny"]), find(&["nz"])],
+            color: [
+                find_color(&["red", "r", "diffuse_red"]),
+                find_color(&["green", "g", "diffuse_green"]),
+                find_color(&["blue", "b", "diffuse_blue"]),
+                find_color(&["alpha", "a"]),
+            ],
+        }
+    }
+
+    fn surfel(&self, values: &[f64]) -> Surfel {
+        let get = |idx: Option<usize>, default: f64| idx.map(|idx| values[idx]).unwrap_or(default) as f32;
+        let channel = |color: Option<(usize, f64)>| color.map(|(idx, scale)| values[idx] * scale).unwrap_or(1.0) as f32;
+        let pos = Vec3::new(get(self.position[0], 0.0), get(self.position[1], 0.0), get(self.position[2], 0.0));
+        let normal = Vec3::new(get(self.normal[0], 0.0), get(self.normal[1], 0.0), get(self.normal[2], 0.0));
+        let color = Color::rgba(channel(self.color[0]), channel(self.color[1]), channel(self.color[2]), channel(self.color[3]));
+        Surfel::new(pos, color, normal)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points() -> Vec<Surfel> {
+        vec![
+            Surfel::new(Vec3::new(1.0, -2.5, 3.25), Color::rgba_255(255, 0, 10, 255), Vec3::Y),
+            Surfel::new(Vec3::new(0.1, 1e6, -0.3), Color::rgba_255(1, 2, 3, 128), Vec3::new(0.6, 0.0, -0.8)),
+        ]
+    }
+
+    #[test]
+    fn round_trip() {
+        for format in [PlyFormat::Ascii, PlyFormat::BinaryLittleEndian, PlyFormat::BinaryBigEndian] {
+            let mut bytes = vec![];
+            write(&mut bytes, &points(), format).unwrap();
+            assert!(read(bytes.as_slice()).unwrap() == points(), "{:?}", format);
+        };
+    }
+
+    #[test]
+    fn reads_other_writers() {
+        // Double positions, no colours, and faces to skip
+        let text = "ply\nformat ascii 1.0\nelement vertex 2\nproperty double x\nproperty double y\nproperty double z\n\
+            element face 1\nproperty list uchar int vertex_indices\nend_header\n1 2 3\n4 5 6\n3 0 1 0\n";
+        let points = read(text.as_bytes()).unwrap();
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[1].pos, Vec3::new(4.0, 5.0, 6.0));
+    }
+
+    #[test]
+    fn rejects_broken_files() {
+        let no_position = "ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\nend_header\n1\n";
+        assert!(matches!(read(no_position.as_bytes()), Err(PlyError::Invalid(_))));
+        assert!(read("not a ply file\n".as_bytes()).is_err());
+
+        let mut bytes = vec![];
+        write(&mut bytes, &points(), PlyFormat::BinaryLittleEndian).unwrap();
+        bytes.truncate(bytes.len() - 1);
+        assert!(read(bytes.as_slice()).is_err());
+    }
+}
@@ -0,0 +1,529 @@
+use std::{fmt, fs::File, io::{self, BufReader, BufWriter, Read, Write}, path::Path};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use glam::{Quat, Vec3};
+
+use crate::{aabb::Aabb, color::Color, drawable::{next_revision, Drawable}, surfel::Surfel};
+
+
+const MAGIC: [u8; 4] = *b"TDPC";
+pub const VERSION: u16 = 1;
+
+const FLAG_EMISSIVE: u8 = 1;
+const FLAG_MATERIAL: u8 = 2;
+
+
+#[derive(Debug)]
+pub enum PointCloudError {
+    Io(io::Error),
+    /// Not a point cloud file at all
+    BadMagic,
+    UnsupportedVersion(u16),
+    ChecksumMismatch {expected: u32, actual: u32},
+}
+
+impl fmt::Display for PointCloudError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PointCloudError::Io(e) => write!(f, "{}", e),
+            PointCloudError::BadMagic => write!(f, "not a point cloud file"),
+            PointCloudError::UnsupportedVersion(version) => write!(f, "unsupported point cloud version {}", version),
+            PointCloudError::ChecksumMismatch {expected, actual} => {
+                write!(f, "checksum mismatch, expected {:08x} but got {:08x}", expected, actual)
+            },
+        }
+    }
+}
+
+impl std::error::Error for PointCloudError {}
+
+impl From<io::Error> for PointCloudError {
+    fn from(e: io::Error) -> Self {
+        PointCloudError::Io(e)
+    }
+}
+
+
+/// One object of a point cloud file.
+#[derive(Clone, PartialEq)]
+pub struct CloudObject {
+    pub origin: Vec3,
+    /// Points in world space
+    pub points: Vec<Surfel>,
+}
+
+
+/// Writes a point cloud file one object at a time, so nothing but the current object
+/// has to be in memory. Everything is little-endian:
+/// ```text
+/// header   magic "TDPC", version u16, reserved u16, object count u32
+/// object   origin 3 x f32, point count u32, then per point:
+///          position relative to the origin 3 x f32, colour RGBA 4 x u8,
+///          normal 3 x i8 (scaled by 127), flags u8, material u16
+/// footer   CRC-32 of everything between the header and the footer, u32
+/// ```
+/// Object ids of surfels are not stored, they are assigned again when the objects are added to a world.
+pub struct PointCloudWriter<W: Write> {
+    inner: Crc32Writer<W>,
+    objects_left: u32,
+    points_left: u32,
+    origin: Vec3,
+}
+
+impl<W: Write> PointCloudWriter<W> {
+    /// Writes the header. Exactly `object_count` objects have to follow before `finish`.
+    pub fn new(mut writer: W, object_count: u32) -> Result<Self, PointCloudError> {
+        writer.write_all(&MAGIC)?;
+        writer.write_u16::<LittleEndian>(VERSION)?;
+        writer.write_u16::<LittleEndian>(0)?;
+        writer.write_u32::<LittleEndian>(object_count)?;
+        Ok(PointCloudWriter {
+            inner: Crc32Writer {inner: writer, crc: Crc32::new()},
+            objects_left: object_count,
+            points_left: 0,
+            origin: Vec3::ZERO,
+        })
+    }
+
+    /// Starts the next object. Exactly `point_count` calls to `write_point` have to follow.
+    pub fn begin_object(&mut self, origin: Vec3, point_count: u32) -> Result<(), PointCloudError> {
+        if self.objects_left == 0 || self.points_left != 0 {
+            return Err(invalid_input("object does not fit the counts given before"));
+        };
+        self.objects_left -= 1;
+        self.points_left = point_count;
+        self.origin = origin;
+        write_vec3(&mut self.inner, origin)?;
+        self.inner.write_u32::<LittleEndian>(point_count)?;
+        Ok(())
+    }
+
+    /// Writes a point of the current object, with its position in world space.
+    pub fn write_point(&mut self, surfel: &Surfel) -> Result<(), PointCloudError> {
+        if self.points_left == 0 {
+            return Err(invalid_input("more points than announced for this object"));
+        };
+        self.points_left -= 1;
+        let w = &mut self.inner;
+        write_vec3(w, surfel.pos - self.origin)?;
+        let c = surfel.color.as_255();
+        for channel in [c.r, c.g, c.b, c.a] {
+            w.write_u8(channel.round().clamp(0.0, 255.0) as u8)?;
+        };
+        for n in surfel.normal.to_array() {
+            w.write_i8((n.clamp(-1.0, 1.0) * 127.0).round() as i8)?;
+        };
+        let mut flags = 0;
+        if surfel.emissive {
+            flags |= FLAG_EMISSIVE;
+        };
+        if surfel.material.is_some() {
+            flags |= FLAG_MATERIAL;
+        };
+        w.write_u8(flags)?;
+        w.write_u16::<LittleEndian>(surfel.material.unwrap_or(0))?;
+        Ok(())
+    }
+
+    pub fn write_object(&mut self, origin: Vec3, points: &[Surfel]) -> Result<(), PointCloudError> {
+        self.begin_object(origin, points.len() as u32)?;
+        for surfel in points {
+            self.write_point(surfel)?;
+        };
+        Ok(())
+    }
+
+    /// Writes the checksum and hands back the underlying writer.
+    pub fn finish(self) -> Result<W, PointCloudError> {
+        if self.objects_left != 0 || self.points_left != 0 {
+            return Err(invalid_input("fewer objects or points written than announced"));
+        };
+        let crc = self.inner.crc.finish();
+        let mut writer = self.inner.inner;
+        writer.write_u32::<LittleEndian>(crc)?;
+        writer.flush()?;
+        Ok(writer)
+    }
+}
+
+
+/// Reads a point cloud file one object at a time. The checksum is verified after the last object.
+pub struct PointCloudReader<R: Read> {
+    inner: Crc32Reader<R>,
+    pub version: u16,
+    pub object_count: u32,
+    objects_left: u32,
+    points_left: u32,
+    origin: Vec3,
+    checked: bool,
+}
+
+impl<R: Read> PointCloudReader<R> {
+    /// Reads and checks the header.
+    pub fn new(mut reader: R) -> Result<Self, PointCloudError> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(PointCloudError::BadMagic);
+        };
+        let version = reader.read_u16::<LittleEndian>()?;
+        if version != VERSION {
+            return Err(PointCloudError::UnsupportedVersion(version));
+        };
+        let _reserved = reader.read_u16::<LittleEndian>()?;
+        let object_count = reader.read_u32::<LittleEndian>()?;
+        Ok(PointCloudReader {
+            inner: Crc32Reader {inner: reader, crc: Crc32::new()},
+            version,
+            object_count,
+            objects_left: object_count,
+            points_left: 0,
+            origin: Vec3::ZERO,
+            checked: false,
+        })
+    }
+
+    /// Starts the next object, returning its origin and number of points.
+    /// `None` once all objects were read and the checksum matched.
+    pub fn begin_object(&mut self) -> Result<Option<(Vec3, u32)>, PointCloudError> {
+        // Skip whatever is left of the previous object
+        while self.points_left > 0 {
+            self.read_point()?;
+        };
+        if self.objects_left == 0 {
+            if !self.checked {
+                self.checked = true;
+                self.verify_checksum()?;
+            };
+            return Ok(None);
+        };
+        self.objects_left -= 1;
+        self.origin = read_vec3(&mut self.inner)?;
+        self.points_left = self.inner.read_u32::<LittleEndian>()?;
+        Ok(Some((self.origin, self.points_left)))
+    }
+
+    /// Next point of the current object with its position in world space,
+    /// `None` at the end of the object.
+    pub fn read_point(&mut self) -> Result<Option<Surfel>, PointCloudError> {
+        if self.points_left == 0 {
+            return Ok(None);
+        };
+        self.points_left -= 1;
+        let r = &mut self.inner;
+        let pos = read_vec3(r)? + self.origin;
+        let mut rgba = [0u8; 4];
+        r.read_exact(&mut rgba)?;
+        let normal = Vec3::new(r.read_i8()? as f32, r.read_i8()? as f32, r.read_i8()? as f32) / 127.0;
+        let flags = r.read_u8()?;
+        let material = r.read_u16::<LittleEndian>()?;
+
+        let mut surfel = Surfel::new(pos, Color::rgba_255(rgba[0], rgba[1], rgba[2], rgba[3]), normal)
+            .with_emissive(flags & FLAG_EMISSIVE != 0);
+        if flags & FLAG_MATERIAL != 0 {
+            surfel = surfel.with_material(material);
+        };
+        Ok(Some(surfel))
+    }
+
+    /// Reads the next whole object.
+    pub fn read_object(&mut self) -> Result<Option<CloudObject>, PointCloudError> {
+        let (origin, count) = match self.begin_object()? {
+            Some(object) => object,
+            None => return Ok(None),
+        };
+        // Don't trust the count for allocating up front, a corrupt file could claim billions
+        let mut points = Vec::with_capacity(count.min(1 << 20) as usize);
+        while let Some(surfel) = self.read_point()? {
+            points.push(surfel);
+        };
+        Ok(Some(CloudObject {origin, points}))
+    }
+
+    fn verify_checksum(&mut self) -> Result<(), PointCloudError> {
+        let actual = self.inner.crc.finish();
+        let expected = self.inner.inner.read_u32::<LittleEndian>()?;
+        match expected == actual {
+            true => Ok(()),
+            false => Err(PointCloudError::ChecksumMismatch {expected, actual}),
+        }
+    }
+}
+
+impl<R: Read> Iterator for PointCloudReader<R> {
+    type Item = Result<CloudObject, PointCloudError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_object().transpose()
+    }
+}
+
+
+/// Writes the points of every object to a point cloud file.
+pub fn save<P: AsRef<Path>>(path: P, objects: &[Box<dyn Drawable>]) -> Result<(), PointCloudError> {
+    let mut writer = PointCloudWriter::new(BufWriter::new(File::create(path)?), objects.len() as u32)?;
+    for object in objects {
+        writer.write_object(object.get_origin(), object.get_points())?;
+    };
+    writer.finish()?;
+    Ok(())
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<CloudObject>, PointCloudError> {
+    PointCloudReader::new(BufReader::new(File::open(path)?))?.collect()
+}
+
+
+/// Drawable made of points loaded from a file rather than generated.
+pub struct PointCloud {
+    points: Vec<Surfel>,
+    local: Vec<Surfel>,
+    origin: Vec3,
+    rotation: Quat,
+    bounds: Aabb,
+    revision: u64,
+}
+
+impl PointCloud {
+    pub fn new(origin: Vec3, points: Vec<Surfel>) -> Self {
+        let bounds = Aabb::from_points(points.iter().map(|pt| &pt.pos));
+        let local = points.iter().map(|pt| Surfel {pos: pt.pos - origin, ..*pt}).collect();
+        PointCloud {
+            points,
+            local,
+            origin,
+            rotation: Quat::IDENTITY,
+            bounds,
+            revision: next_revision(),
+        }
+    }
+
+    /// All objects of a file merged into one, with its origin at `origin`.
+    pub fn merged(objects: Vec<CloudObject>, origin: Vec3) -> Self {
+        let points = objects.into_iter().flat_map(|object| object.points).collect();
+        PointCloud::new(origin, points)
+    }
+
+    pub fn rotation(&self) -> Quat {
+        self.rotation
+    }
+
+    /// Rebuilds the points from the unrotated ones around the origin, so turning the cloud
+    /// many times does not add up rounding errors.
+    fn place(&mut self) {
+        for (pt, local) in self.points.iter_mut().zip(self.local.iter()) {
+            pt.pos = self.origin + self.rotation * local.pos;
+            pt.normal = self.rotation * local.normal;
+        };
+        self.bounds = Aabb::from_points(self.points.iter().map(|pt| &pt.pos));
+        self.revision = next_revision();
+    }
+}
+
+impl From<CloudObject> for PointCloud {
+    fn from(object: CloudObject) -> Self {
+        PointCloud::new(object.origin, object.points)
+    }
+}
+
+impl Drawable for PointCloud {
+    fn get_points(&self) -> &Vec<Surfel> {
+        &self.points
+    }
+
+    fn get_bounds(&self) -> Aabb {
+        self.bounds
+    }
+
+    fn get_origin(&self) -> Vec3 {
+        self.origin
+    }
+
+    fn set_origin(&mut self, origin: Vec3) {
+        if origin == self.origin {
+            return;
+        };
+        self.origin = origin;
+        self.place();
+    }
+
+    fn set_rotation(&mut self, rotation: Quat) {
+        if rotation == self.rotation {
+            return;
+        };
+        self.rotation = rotation.normalize();
+        self.place();
+    }
+
+    fn revision(&self) -> u64 {
+        self.revision
+    }
+}
+
+
+fn invalid_input(message: &str) -> PointCloudError {
+    PointCloudError::Io(io::Error::new(io::ErrorKind::InvalidInput, message))
+}
+
+fn write_vec3<W: Write>(w: &mut W, v: Vec3) -> io::Result<()> {
+    w.write_f32::<LittleEndian>(v.x)?;
+    w.write_f32::<LittleEndian>(v.y)?;
+    w.write_f32::<LittleEndian>(v.z)
+}
+
+fn read_vec3<R: Read>(r: &mut R) -> io::Result<Vec3> {
+    Ok(Vec3::new(r.read_f32::<LittleEndian>()?, r.read_f32::<LittleEndian>()?, r.read_f32::<LittleEndian>()?))
+}
+
+
+/// CRC-32 as used by zip and png.
+struct Crc32 {
+    value: u32,
+}
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = match c & 1 {
+                1 => 0xEDB8_8320 ^ (c >> 1),
+                _ => c >> 1,
+            };
+            k += 1;
+        };
+        table[i] = c;
+        i += 1;
+    };
+    table
+};
+
+impl Crc32 {
+    fn new() -> Self {
+        Crc32 {value: 0xFFFF_FFFF}
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.value = CRC_TABLE[((self.value ^ *byte as u32) & 0xFF) as usize] ^ (self.value >> 8);
+        };
+    }
+
+    fn finish(&self) -> u32 {
+        !self.value
+    }
+}
+
+struct Crc32Writer<W: Write> {
+    inner: W,
+    crc: Crc32,
+}
+
+impl<W: Write> Write for Crc32Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.crc.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+struct Crc32Reader<R: Read> {
+    inner: R,
+    crc: Crc32,
+}
+
+impl<R: Read> Read for Crc32Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.crc.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn objects() -> Vec<CloudObject> {
+        let color = Color::rgba_255(200, 100, 50, 255);
+        vec![
+            CloudObject {
+                origin: Vec3::new(10.0, 0.0, -4.0),
+                points: vec![
+                    Surfel::new(Vec3::new(11.0, 2.0, -4.0), color, Vec3::Y),
+                    Surfel::new(Vec3::new(9.5, 0.0, -3.0), color, Vec3::X).with_emissive(true),
+                    Surfel::new(Vec3::new(10.0, 1.0, -4.5), color, Vec3::Z).with_material(7),
+                ],
+            },
+            CloudObject {origin: Vec3::ZERO, points: vec![]},
+        ]
+    }
+
+    fn write(objects: &[CloudObject]) -> Vec<u8> {
+        let mut writer = PointCloudWriter::new(vec![], objects.len() as u32).unwrap();
+        for object in objects {
+            writer.write_object(object.origin, &object.points).unwrap();
+        };
+        writer.finish().unwrap()
+    }
+
+    fn read(bytes: &[u8]) -> Result<Vec<CloudObject>, PointCloudError> {
+        PointCloudReader::new(bytes)?.collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let objects = objects();
+        assert!(read(&write(&objects)).unwrap() == objects);
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let mut bytes = write(&objects());
+        // Flip a bit in the colour of the first point, past the header
+        bytes[12 + 16 + 12] ^= 1;
+        assert!(matches!(read(&bytes), Err(PointCloudError::ChecksumMismatch {..})));
+    }
+
+    #[test]
+    fn truncated() {
+        let bytes = write(&objects());
+        for len in 0..bytes.len() {
+            assert!(matches!(read(&bytes[..len]), Err(PointCloudError::Io(_))), "{} of {} bytes", len, bytes.len());
+        };
+    }
+
+    #[test]
+    fn bad_header() {
+        let mut bytes = write(&objects());
+        bytes[4] = 2;
+        assert!(matches!(read(&bytes), Err(PointCloudError::UnsupportedVersion(2))));
+        bytes[0] = b'X';
+        assert!(matches!(read(&bytes), Err(PointCloudError::BadMagic)));
+    }
+
+    #[test]
+    fn spinning_keeps_shape() {
+        let object = objects().remove(0);
+        let mut spun = PointCloud::from(object.clone());
+        for step in 1..=2000 {
+            spun.set_rotation(Quat::from_rotation_y(step as f32 * 0.01) * Quat::from_rotation_x(step as f32 * 0.003));
+            spun.set_origin(object.origin + Vec3::new(step as f32 * 0.1, 0.0, 0.0));
+        };
+        let rotation = spun.rotation();
+        let mut turned = PointCloud::from(object);
+        turned.set_rotation(rotation);
+        turned.set_origin(spun.get_origin());
+        for (a, b) in spun.get_points().iter().zip(turned.get_points()) {
+            assert!(a.pos.abs_diff_eq(b.pos, 1e-4), "{} vs {}", a.pos, b.pos);
+            assert!(a.normal.abs_diff_eq(b.normal, 1e-6));
+        };
+    }
+}
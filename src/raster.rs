@@ -0,0 +1,518 @@
+use std::ops::Range;
+
+use glam::{Affine3A, Mat3, Vec2, Vec3, Vec4};
+
+use crate::{camera::Camera, picking::NO_OBJECT, surfel::Surfel};
+
+
+/// Points handed to one worker at a time. Small enough to spread work evenly, large enough
+/// that handing it out costs nothing next to transforming it.
+const CHUNK_POINTS: usize = 4096;
+/// Below this many points per thread, starting the threads costs more than it saves
+const MIN_POINTS_PER_THREAD: usize = 16 * 1024;
+
+
+/// Camera, screen and light, everything that decides where a point ends up and how bright.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct View {
+    pub rotation: Mat3,
+    pub target: Vec3,
+    pub zoom: f32,
+    /// Middle of the render target in pixels, with z 0
+    pub center: Vec3,
+    pub width: usize,
+    pub height: usize,
+    pub light_dir: Vec3,
+    pub light_intensity: f32,
+}
+
+impl View {
+    pub fn new(camera: &Camera, width: usize, height: usize, light_dir: Vec3, light_intensity: f32) -> Self {
+        View {
+            rotation: camera.rotation(),
+            target: camera.target,
+            zoom: camera.zoom,
+            center: (Vec2::new(width as f32, height as f32) * 0.5).extend(0.0),
+            width,
+            height,
+            light_dir,
+            light_intensity,
+        }
+    }
+
+    /// Screen position of a world space point, with z growing towards the viewer.
+    pub fn to_screen(&self, point: Vec3) -> Vec3 {
+        self.rotation * (point - self.target) * self.zoom + self.center
+    }
+
+    /// `to_screen` for points shifted by `offset`, as a single matrix for `Positions::transform`.
+    pub fn matrix(&self, offset: Vec3) -> Affine3A {
+        Affine3A::from_translation(self.center)
+            * Affine3A::from_mat3(self.rotation * self.zoom)
+            * Affine3A::from_translation(offset - self.target)
+    }
+
+    /// Colour of a surfel under the light, as 0..=255 RGBA.
+    pub fn shade(&self, surfel: &Surfel) -> [u8; 4] {
+        let mut color = surfel.color;
+        if !surfel.emissive {
+            color *= (surfel.normal.dot(self.light_dir) * self.light_intensity).clamp(0.0, 1.0);
+        };
+        let c = color.as_255();
+        [c.r as u8, c.g as u8, c.b as u8, c.a as u8]
+    }
+
+    /// Pixel a screen position falls on, `None` when it is off screen.
+    pub fn pixel(&self, screen: Vec3) -> Option<(usize, usize)> {
+        let in_bounds = (screen.x >= -1.0 && screen.x <= self.width as f32)
+            && (screen.y >= -1.0 && screen.y <= self.height as f32);
+        let (x, y) = (screen.x.round() as usize, screen.y.round() as usize);
+        match in_bounds && x < self.width && y < self.height {
+            true => Some((x, y)),
+            false => None,
+        }
+    }
+
+    /// Where and how a surfel of object `object_id` is drawn, given its screen position.
+    pub fn fragment(&self, surfel: &Surfel, screen: Vec3, object_id: u32) -> Option<Fragment> {
+        let (x, y) = self.pixel(screen)?;
+        Some(Fragment {
+            x: x as u32,
+            y: y as u32,
+            depth: screen.z,
+            color: self.shade(surfel),
+            object_id: surfel.object_id.unwrap_or(object_id),
+        })
+    }
+}
+
+
+/// A point that made it onto the screen, ready to be depth tested and written.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fragment {
+    pub x: u32,
+    pub y: u32,
+    /// Screen z, larger is closer
+    pub depth: f32,
+    pub color: [u8; 4],
+    pub object_id: u32,
+}
+
+
+/// Point positions as separate x, y and z arrays, so they can be transformed four at a time.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Positions {
+    pub xs: Vec<f32>,
+    pub ys: Vec<f32>,
+    pub zs: Vec<f32>,
+}
+
+impl Positions {
+    pub fn from_surfels(points: &[Surfel]) -> Self {
+        Positions {
+            xs: points.iter().map(|point| point.pos.x).collect(),
+            ys: points.iter().map(|point| point.pos.y).collect(),
+            zs: points.iter().map(|point| point.pos.z).collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.xs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.xs.is_empty()
+    }
+
+    pub fn get(&self, idx: usize) -> Vec3 {
+        Vec3::new(self.xs[idx], self.ys[idx], self.zs[idx])
+    }
+
+    /// Writes the points in `range` transformed by `matrix` to `out`, replacing what it held.
+    pub fn transform(&self, matrix: &Affine3A, range: Range<usize>, out: &mut Positions) {
+        let (xs, ys, zs) = (&self.xs[range.clone()], &self.ys[range.clone()], &self.zs[range]);
+        let len = xs.len();
+        out.xs.resize(len, 0.0);
+        out.ys.resize(len, 0.0);
+        out.zs.resize(len, 0.0);
+
+        let m = matrix.matrix3;
+        let t = matrix.translation;
+        let rows = [
+            (Vec4::splat(m.x_axis.x), Vec4::splat(m.y_axis.x), Vec4::splat(m.z_axis.x), Vec4::splat(t.x)),
+            (Vec4::splat(m.x_axis.y), Vec4::splat(m.y_axis.y), Vec4::splat(m.z_axis.y), Vec4::splat(t.y)),
+            (Vec4::splat(m.x_axis.z), Vec4::splat(m.y_axis.z), Vec4::splat(m.z_axis.z), Vec4::splat(t.z)),
+        ];
+        let simd_len = len - len % 4;
+        for idx in (0..simd_len).step_by(4) {
+            let x = Vec4::from_slice(&xs[idx..]);
+            let y = Vec4::from_slice(&ys[idx..]);
+            let z = Vec4::from_slice(&zs[idx..]);
+            for (row, out) in rows.iter().zip([&mut out.xs, &mut out.ys, &mut out.zs]) {
+                (row.0 * x + row.1 * y + row.2 * z + row.3).write_to_slice(&mut out[idx..]);
+            };
+        };
+        for idx in simd_len..len {
+            let point = matrix.transform_point3(Vec3::new(xs[idx], ys[idx], zs[idx]));
+            out.xs[idx] = point.x;
+            out.ys[idx] = point.y;
+            out.zs[idx] = point.z;
+        };
+    }
+}
+
+
+/// The points of one object, drawn `offset` away from where they are.
+#[derive(Clone, Copy)]
+pub struct Batch<'a> {
+    pub points: &'a [Surfel],
+    /// Positions of `points`, as kept up to date by the caller
+    pub positions: &'a Positions,
+    /// Revision of the object the points belong to, see `Drawable::revision`
+    pub revision: u64,
+    pub offset: Vec3,
+    /// Id for surfels that don't carry their own
+    pub object_id: u32,
+}
+
+
+/// Draws points into an RGBA frame and optionally an id buffer, using up to `threads` threads.
+/// See `Renderer` for drawing frame after frame.
+pub fn draw(view: &View, batches: &[Batch], threads: usize, frame: &mut [u8], ids: Option<&mut [u32]>) {
+    Renderer::new(threads).draw(view, batches, frame, ids);
+}
+
+
+/// Fragments of one object as drawn last time, sorted into tiles.
+struct CachedObject {
+    revision: u64,
+    len: usize,
+    offset: Vec3,
+    view: View,
+    tile_rows: usize,
+    tiles: Vec<Vec<Fragment>>,
+}
+
+/// The last finished frame, handed out again as long as nothing in it changed.
+#[derive(Default)]
+struct CachedFrame {
+    objects: Vec<u32>,
+    frame: Vec<u8>,
+    ids: Option<Vec<u32>>,
+}
+
+
+/// Draws points frame after frame, only redoing the work for what changed.
+///
+/// Points are transformed, projected and lit in chunks spread over the threads, each sorting its
+/// fragments into horizontal tiles of the screen. Every tile is then depth tested on its own
+/// thread. The closest point wins a pixel, and among equally close points the one given last, so
+/// the result does not depend on the number of threads.
+///
+/// The fragments of each object are kept until its revision, offset or the view changes. Revisions
+/// are never handed out twice, so an object replaced by another under the same `object_id` is
+/// drawn anew, and revision `0` is never cached. When nothing changed at all, the previous frame
+/// is copied out as is.
+pub struct Renderer {
+    pub threads: usize,
+    objects: Vec<Option<CachedObject>>,
+    frame: Option<CachedFrame>,
+}
+
+impl Renderer {
+    pub fn new(threads: usize) -> Self {
+        Renderer {
+            threads: threads.max(1),
+            objects: vec![],
+            frame: None,
+        }
+    }
+
+    /// Forgets everything drawn so far.
+    pub fn clear(&mut self) {
+        self.objects.clear();
+        self.frame = None;
+    }
+
+    fn is_cached(&self, view: &View, tile_rows: usize, batch: &Batch) -> bool {
+        match self.objects.get(batch.object_id as usize) {
+            Some(Some(cached)) => {
+                batch.revision != 0
+                    && cached.revision == batch.revision
+                    && cached.len == batch.points.len()
+                    && cached.offset == batch.offset
+                    && cached.view == *view
+                    && cached.tile_rows == tile_rows
+            },
+            _ => false,
+        }
+    }
+
+    pub fn draw(&mut self, view: &View, batches: &[Batch], frame: &mut [u8], ids: Option<&mut [u32]>) {
+        let mut ids = ids;
+        if view.width == 0 || view.height == 0 {
+            frame.fill(0);
+            if let Some(ids) = ids.as_mut() {
+                ids.fill(NO_OBJECT);
+            };
+            return;
+        };
+        let tile_rows = view.height.div_ceil(self.threads);
+        let tiles = view.height.div_ceil(tile_rows);
+
+        let stale: Vec<&Batch> = batches.iter()
+            .filter(|batch| !self.is_cached(view, tile_rows, batch))
+            .collect();
+        let objects: Vec<u32> = batches.iter().map(|batch| batch.object_id).collect();
+        let unchanged = match self.frame.as_ref() {
+            Some(cached) => stale.is_empty()
+                && cached.objects == objects
+                && cached.frame.len() == frame.len()
+                && match (ids.as_ref(), cached.ids.as_ref()) {
+                    (Some(ids), Some(cached_ids)) => ids.len() == cached_ids.len(),
+                    (Some(_), None) => false,
+                    (None, _) => true,
+                },
+            None => false,
+        };
+        if let (true, Some(cached)) = (unchanged, self.frame.as_ref()) {
+            frame.copy_from_slice(&cached.frame);
+            if let (Some(ids), Some(cached_ids)) = (ids, cached.ids.as_ref()) {
+                ids.copy_from_slice(cached_ids);
+            };
+            return;
+        };
+
+        // Transform the stale objects in chunks, spread over the threads in drawing order
+        let total: usize = stale.iter().map(|batch| batch.points.len()).sum();
+        let threads = self.threads.min(total / MIN_POINTS_PER_THREAD).max(1);
+        let mut chunks = vec![];
+        for (stale_idx, batch) in stale.iter().enumerate() {
+            for start in (0..batch.points.len()).step_by(CHUNK_POINTS) {
+                chunks.push((stale_idx, start..(start + CHUNK_POINTS).min(batch.points.len())));
+            };
+        };
+        let bin = |chunks: &[(usize, Range<usize>)]| {
+            let mut screen = Positions::default();
+            chunks.iter().map(|(stale_idx, range)| {
+                let batch = stale[*stale_idx];
+                let mut bins = vec![vec![]; tiles];
+                batch.positions.transform(&view.matrix(batch.offset), range.clone(), &mut screen);
+                for (idx, surfel) in batch.points[range.clone()].iter().enumerate() {
+                    if let Some(fragment) = view.fragment(surfel, screen.get(idx), batch.object_id) {
+                        bins[fragment.y as usize / tile_rows].push(fragment);
+                    };
+                };
+                (*stale_idx, bins)
+            }).collect::<Vec<_>>()
+        };
+        let binned: Vec<(usize, Vec<Vec<Fragment>>)> = match threads {
+            1 => bin(&chunks),
+            _ => std::thread::scope(|scope| {
+                let workers: Vec<_> = chunks.chunks(chunks.len().div_ceil(threads))
+                    .map(|run| scope.spawn(move || bin(run)))
+                    .collect();
+                workers.into_iter().flat_map(|worker| worker.join().expect("raster worker panicked")).collect()
+            }),
+        };
+
+        let mut fresh: Vec<CachedObject> = stale.iter()
+            .map(|batch| CachedObject {
+                revision: batch.revision,
+                len: batch.points.len(),
+                offset: batch.offset,
+                view: *view,
+                tile_rows,
+                tiles: vec![vec![]; tiles],
+            })
+            .collect();
+        for (stale_idx, bins) in binned {
+            for (tile, fragments) in fresh[stale_idx].tiles.iter_mut().zip(bins) {
+                tile.extend(fragments);
+            };
+        };
+        // Objects that weren't drawn this time are dropped, their fragments would be stale by the
+        // time they are back on screen
+        let mut objects_cache: Vec<Option<CachedObject>> = vec![];
+        objects_cache.resize_with(batches.iter().map(|batch| batch.object_id as usize + 1).max().unwrap_or(0), || None);
+        for batch in batches.iter() {
+            let idx = batch.object_id as usize;
+            objects_cache[idx] = self.objects.get_mut(idx).and_then(|cached| cached.take());
+        };
+        for (batch, cached) in stale.iter().zip(fresh.drain(..)) {
+            objects_cache[batch.object_id as usize] = Some(cached);
+        };
+        self.objects = objects_cache;
+
+        self.redraw(view, batches, tile_rows, frame, ids);
+    }
+
+    /// Depth tests the cached fragments of `batches` into the frame, tile by tile.
+    fn redraw(&mut self, view: &View, batches: &[Batch], tile_rows: usize, frame: &mut [u8], ids: Option<&mut [u32]>) {
+        let total: usize = batches.iter().map(|batch| batch.points.len()).sum();
+        let threads = self.threads.min(total / MIN_POINTS_PER_THREAD).max(1);
+        let tiles = view.height.div_ceil(tile_rows);
+        let cached: Vec<&CachedObject> = batches.iter()
+            .filter_map(|batch| self.objects.get(batch.object_id as usize).and_then(|cached| cached.as_ref()))
+            .collect();
+
+        let resolve = |tile: usize, frame: &mut [u8], mut ids: Option<&mut [u32]>| {
+            frame.fill(0);
+            if let Some(ids) = ids.as_mut() {
+                ids.fill(NO_OBJECT);
+            };
+            let first_row = tile * tile_rows;
+            let mut depth = vec![f32::NEG_INFINITY; frame.len() / 4];
+            for fragment in cached.iter().flat_map(|object| object.tiles[tile].iter()) {
+                let idx = fragment.x as usize + (fragment.y as usize - first_row) * view.width;
+                if fragment.depth >= depth[idx] {
+                    depth[idx] = fragment.depth;
+                    frame[idx * 4..idx * 4 + 4].copy_from_slice(&fragment.color);
+                    if let Some(ids) = ids.as_mut() {
+                        ids[idx] = fragment.object_id;
+                    };
+                };
+            };
+        };
+        let frame_tiles = frame.chunks_mut(tile_rows * view.width * 4);
+        let mut ids = ids;
+        let id_tiles: Vec<Option<&mut [u32]>> = match ids.as_deref_mut() {
+            Some(ids) => ids.chunks_mut(tile_rows * view.width).map(Some).collect(),
+            None => (0..tiles).map(|_| None).collect(),
+        };
+        match threads {
+            1 => {
+                for (tile, (frame, ids)) in frame_tiles.zip(id_tiles).enumerate() {
+                    resolve(tile, frame, ids);
+                };
+            },
+            _ => std::thread::scope(|scope| {
+                for (tile, (frame, ids)) in frame_tiles.zip(id_tiles).enumerate() {
+                    let resolve = &resolve;
+                    scope.spawn(move || resolve(tile, frame, ids));
+                };
+            }),
+        };
+
+        // Refill the buffers of the last frame rather than allocating new ones every frame
+        let mut cached = self.frame.take().unwrap_or_default();
+        cached.objects.clear();
+        cached.objects.extend(batches.iter().map(|batch| batch.object_id));
+        cached.frame.clear();
+        cached.frame.extend_from_slice(frame);
+        match ids {
+            Some(ids) => {
+                let cached_ids = cached.ids.get_or_insert_with(Vec::new);
+                cached_ids.clear();
+                cached_ids.extend_from_slice(ids);
+            },
+            None => cached.ids = None,
+        };
+        self.frame = Some(cached);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{color::Color, rng::Rng};
+
+    const WIDTH: usize = 64;
+    const HEIGHT: usize = 45;
+
+    fn view() -> View {
+        let camera = Camera {yaw: 0.4, pitch: 0.7, zoom: 1.5, ..Camera::default()};
+        View::new(&camera, WIDTH, HEIGHT, Vec3::new(-0.5, -0.5, 1.0).normalize(), 1.0)
+    }
+
+    fn surfels(rng: &mut Rng, count: usize) -> Vec<Surfel> {
+        (0..count).map(|_| {
+            let pos = Vec3::new(rng.range_f32(-30.0, 30.0), rng.range_f32(-30.0, 30.0), rng.range_f32(-30.0, 30.0));
+            let color = Color::rgb(rng.next_f32(), rng.next_f32(), rng.next_f32());
+            let normal = Vec3::new(rng.range_f32(-1.0, 1.0), rng.range_f32(-1.0, 1.0), 1.0).normalize();
+            Surfel::new(pos, color, normal)
+        }).collect()
+    }
+
+    fn batches<'a>(objects: &'a [(Vec<Surfel>, Positions)], offsets: &[Vec3]) -> Vec<Batch<'a>> {
+        objects.iter().zip(offsets).enumerate().map(|(idx, ((points, positions), offset))| Batch {
+            points,
+            positions,
+            revision: idx as u64 + 1,
+            offset: *offset,
+            object_id: idx as u32,
+        }).collect()
+    }
+
+    fn objects(counts: &[usize]) -> Vec<(Vec<Surfel>, Positions)> {
+        let mut rng = Rng::new(11);
+        counts.iter().map(|count| {
+            let points = surfels(&mut rng, *count);
+            let positions = Positions::from_surfels(&points);
+            (points, positions)
+        }).collect()
+    }
+
+    fn render(renderer: &mut Renderer, batches: &[Batch]) -> (Vec<u8>, Vec<u32>) {
+        let mut frame = vec![0u8; WIDTH * HEIGHT * 4];
+        let mut ids = vec![0u32; WIDTH * HEIGHT];
+        renderer.draw(&view(), batches, &mut frame, Some(&mut ids));
+        (frame, ids)
+    }
+
+    #[test]
+    fn transform_matches_to_screen() {
+        let view = view();
+        let offset = Vec3::new(3.0, -1.0, 2.0);
+        let points = surfels(&mut Rng::new(5), 1023);
+        let positions = Positions::from_surfels(&points);
+        let mut out = Positions::default();
+        // Lengths not divisible by 4, starting off the 4-aligned grid, so the scalar tail runs too
+        for range in [0..1023, 1..8, 5..6, 0..0] {
+            positions.transform(&view.matrix(offset), range.clone(), &mut out);
+            assert_eq!(out.len(), range.len());
+            for (idx, point) in points[range].iter().enumerate() {
+                let expected = view.to_screen(point.pos + offset);
+                assert!(out.get(idx).abs_diff_eq(expected, 1e-3), "{} vs {}", out.get(idx), expected);
+            };
+        };
+    }
+
+    #[test]
+    fn threads_match_single_thread() {
+        // Enough points for every thread to get work, so tiles really are drawn in parallel
+        let objects = objects(&[40_000, 30_000, 3]);
+        let offsets = [Vec3::ZERO, Vec3::new(2.0, 0.0, -1.0), Vec3::ONE];
+        let batches = batches(&objects, &offsets);
+        let single = render(&mut Renderer::new(1), &batches);
+        assert!(single.1.contains(&0) && single.1.contains(&1));
+        for threads in [2, 3, 4, 7] {
+            assert!(render(&mut Renderer::new(threads), &batches) == single, "{} threads", threads);
+        };
+    }
+
+    #[test]
+    fn cached_frames_match_fresh() {
+        let objects = objects(&[500, 800, 300]);
+        let mut offsets = [Vec3::ZERO, Vec3::new(2.0, 0.0, -1.0), Vec3::new(-5.0, 1.0, 0.0)];
+        let mut renderer = Renderer::new(2);
+        let first = render(&mut renderer, &batches(&objects, &offsets));
+        // Nothing changed, the whole frame comes from the cache
+        assert!(render(&mut renderer, &batches(&objects, &offsets)) == first);
+
+        // One object moved, the others come from the cache
+        offsets[1] = Vec3::new(0.0, 3.0, 0.0);
+        let moved = batches(&objects, &offsets);
+        assert!(render(&mut renderer, &moved) == render(&mut Renderer::new(2), &moved));
+
+        // One object left the screen and came back
+        assert!(render(&mut renderer, &moved[..2]) == render(&mut Renderer::new(2), &moved[..2]));
+        assert!(render(&mut renderer, &moved) == render(&mut Renderer::new(2), &moved));
+
+        // Another object took over an id with a new revision
+        let mut replaced = moved.clone();
+        replaced[0] = Batch {points: &objects[2].0, positions: &objects[2].1, revision: 100, ..moved[0]};
+        assert!(render(&mut renderer, &replaced) == render(&mut Renderer::new(2), &replaced));
+    }
+}
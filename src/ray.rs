@@ -0,0 +1,59 @@
+use glam::Vec3;
+
+use crate::aabb::Aabb;
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ray {
+    pub origin: Vec3,
+    /// Always normalized, so distances along the ray are in world units.
+    pub dir: Vec3,
+}
+
+impl Ray {
+    pub fn new(origin: Vec3, dir: Vec3) -> Self {
+        Ray {
+            origin,
+            dir: dir.normalize_or_zero(),
+        }
+    }
+
+    pub fn at(&self, t: f32) -> Vec3 {
+        self.origin + self.dir * t
+    }
+
+    /// Distance at which the ray enters the box (`0` if it starts inside), if it hits at all.
+    pub fn intersect_aabb(&self, aabb: &Aabb) -> Option<f32> {
+        if aabb.is_empty() {
+            return None;
+        };
+        let inv = self.dir.recip();
+        let t1 = (aabb.min - self.origin) * inv;
+        let t2 = (aabb.max - self.origin) * inv;
+        let t_near = t1.min(t2).max_element();
+        let t_far = t1.max(t2).min_element();
+        match t_far >= t_near.max(0.0) {
+            true => Some(t_near.max(0.0)),
+            false => None,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intersect_aabb() {
+        let aabb = Aabb::new(Vec3::new(2.0, -1.0, -1.0), Vec3::new(4.0, 1.0, 1.0));
+        assert_eq!(Ray::new(Vec3::ZERO, Vec3::X).intersect_aabb(&aabb), Some(2.0));
+        // The direction is normalized, distances are in world units
+        assert_eq!(Ray::new(Vec3::ZERO, Vec3::X * 10.0).intersect_aabb(&aabb), Some(2.0));
+        // Starting inside, behind, and passing beside
+        assert_eq!(Ray::new(Vec3::new(3.0, 0.0, 0.0), Vec3::X).intersect_aabb(&aabb), Some(0.0));
+        assert_eq!(Ray::new(Vec3::ZERO, -Vec3::X).intersect_aabb(&aabb), None);
+        assert_eq!(Ray::new(Vec3::new(0.0, 2.0, 0.0), Vec3::X).intersect_aabb(&aabb), None);
+        assert_eq!(Ray::new(Vec3::ZERO, Vec3::X).intersect_aabb(&Aabb::empty()), None);
+    }
+}
@@ -0,0 +1,114 @@
+use randomize::{PCG32, RandRangeU32, f32_half_open_right};
+
+
+/// Small seeded random number generator. The same seed always produces the same sequence,
+/// so anything generated from it (noise tables, levels) is reproducible across runs.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    pcg: PCG32,
+    seed: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng {
+            pcg: PCG32::seed(seed, seed | 1),
+            seed,
+        }
+    }
+
+    /// Seeds the generator from the operating system. Falls back to a fixed seed if that fails.
+    pub fn from_entropy() -> Self {
+        let mut buf = [0u8; 8];
+        let seed = match getrandom::getrandom(&mut buf) {
+            Ok(_) => u64::from_le_bytes(buf),
+            Err(e) => {
+                log::warn!("getrandom failed ({}), using a fixed seed", e);
+                0x5EED
+            },
+        };
+        Rng::new(seed)
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        self.pcg.next_u32()
+    }
+
+    /// Uniform float in `[0.0, 1.0)`.
+    pub fn next_f32(&mut self) -> f32 {
+        f32_half_open_right(self.pcg.next_u32())
+    }
+
+    /// Uniform float in `[min, max)`.
+    pub fn range_f32(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next_f32()
+    }
+
+    /// Uniform integer in `[min, max]` (inclusive, either order).
+    pub fn range_u32(&mut self, min: u32, max: u32) -> u32 {
+        // The full range has a width of 2^32, which doesn't fit the u32 that `RandRangeU32` keeps
+        if min.min(max) == 0 && min.max(max) == u32::MAX {
+            return self.next_u32();
+        };
+        RandRangeU32::new(min, max).sample(&mut self.pcg)
+    }
+
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = self.range_u32(0, i as u32) as usize;
+            items.swap(i, j);
+        };
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_sequence() {
+        let (mut a, mut b) = (Rng::new(42), Rng::new(42));
+        for _ in 0..100 {
+            assert_eq!(a.next_u32(), b.next_u32());
+            assert_eq!(a.next_f32(), b.next_f32());
+            assert_eq!(a.range_u32(3, 9), b.range_u32(3, 9));
+        };
+        let (mut a, mut c) = (Rng::new(42), Rng::new(43));
+        assert!((0..10).any(|_| a.next_u32() != c.next_u32()));
+    }
+
+    #[test]
+    fn ranges_stay_in_bounds() {
+        let mut rng = Rng::new(7);
+        for _ in 0..1000 {
+            assert!((5..=9).contains(&rng.range_u32(9, 5)));
+            let f = rng.range_f32(-2.0, 3.0);
+            assert!((-2.0..3.0).contains(&f));
+        };
+        assert_eq!(rng.range_u32(4, 4), 4);
+    }
+
+    #[test]
+    fn full_u32_range() {
+        let mut rng = Rng::new(1);
+        for _ in 0..10 {
+            rng.range_u32(0, u32::MAX);
+            rng.range_u32(u32::MAX, 0);
+        };
+    }
+
+    #[test]
+    fn shuffle_keeps_items() {
+        let mut items: Vec<u32> = (0..50).collect();
+        Rng::new(3).shuffle(&mut items);
+        let mut sorted = items.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, (0..50).collect::<Vec<_>>());
+        assert_ne!(items, sorted);
+    }
+}
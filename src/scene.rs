@@ -0,0 +1,232 @@
+use glam::{Affine3A, Quat, Vec3};
+
+use crate::{components::Transform, ecs::{Ecs, Entity}};
+
+
+/// The entity this one is attached to. Its `Transform` is then relative to the parent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Parent(pub Entity);
+
+
+/// Entities attached to this one, kept in sync with their `Parent` by `set_parent`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Children(pub Vec<Entity>);
+
+
+/// World space matrix of an entity, the product of its own and all of its ancestors' transforms.
+/// Written by `propagate_transforms`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GlobalTransform(pub Affine3A);
+
+impl Default for GlobalTransform {
+    fn default() -> Self {
+        GlobalTransform(Affine3A::IDENTITY)
+    }
+}
+
+impl GlobalTransform {
+    pub fn position(&self) -> Vec3 {
+        self.0.translation.into()
+    }
+
+    pub fn rotation(&self) -> Quat {
+        self.0.to_scale_rotation_translation().1
+    }
+}
+
+
+pub fn parent(ecs: &Ecs, entity: Entity) -> Option<Entity> {
+    ecs.get::<Parent>(entity).map(|parent| parent.0)
+}
+
+pub fn children(ecs: &Ecs, entity: Entity) -> &[Entity] {
+    match ecs.get::<Children>(entity) {
+        Some(children) => &children.0,
+        None => &[],
+    }
+}
+
+/// Whether `ancestor` is `entity` itself or somewhere above it.
+pub fn is_ancestor(ecs: &Ecs, ancestor: Entity, entity: Entity) -> bool {
+    let mut current = Some(entity);
+    while let Some(e) = current {
+        if e == ancestor {
+            return true;
+        };
+        current = parent(ecs, e);
+    };
+    false
+}
+
+/// World space matrix of an entity, walking up the hierarchy instead of using `GlobalTransform`.
+pub fn world_matrix(ecs: &Ecs, entity: Entity) -> Affine3A {
+    let local = ecs.get::<Transform>(entity).map(|transform| transform.matrix()).unwrap_or(Affine3A::IDENTITY);
+    match parent(ecs, entity) {
+        Some(parent) => world_matrix(ecs, parent) * local,
+        None => local,
+    }
+}
+
+/// Attaches `child` to `parent`, or detaches it with `None`. The child keeps its place in the world,
+/// its `Transform` is rewritten relative to the new parent.
+/// Returns false when that would make an entity its own ancestor or either entity is dead.
+pub fn set_parent(ecs: &mut Ecs, child: Entity, parent: Option<Entity>) -> bool {
+    if !ecs.is_alive(child) {
+        return false;
+    };
+    if let Some(parent) = parent {
+        if !ecs.is_alive(parent) || is_ancestor(ecs, child, parent) {
+            return false;
+        };
+    };
+
+    let world = world_matrix(ecs, child);
+    if let Some(Parent(old)) = ecs.remove::<Parent>(child) {
+        if let Some(siblings) = ecs.get_mut::<Children>(old) {
+            siblings.0.retain(|e| *e != child);
+        };
+    };
+
+    let local = match parent {
+        Some(parent) => {
+            ecs.insert(child, Parent(parent));
+            match ecs.get_mut::<Children>(parent) {
+                Some(children) => children.0.push(child),
+                None => ecs.insert(parent, Children(vec![child])),
+            };
+            world_matrix(ecs, parent).inverse() * world
+        },
+        None => world,
+    };
+    let (_, rotation, position) = local.to_scale_rotation_translation();
+    ecs.insert(child, Transform {position, rotation});
+    true
+}
+
+/// Despawns the entity and everything attached to it.
+pub fn despawn_recursive(ecs: &mut Ecs, entity: Entity) {
+    set_parent(ecs, entity, None);
+    let mut stack = vec![entity];
+    while let Some(e) = stack.pop() {
+        stack.extend_from_slice(children(ecs, e));
+        ecs.despawn(e);
+    };
+}
+
+/// Updates the `GlobalTransform` of every entity with a `Transform`, parents before children.
+pub fn propagate_transforms(ecs: &mut Ecs) {
+    let roots: Vec<Entity> = ecs.with::<Transform>()
+        .into_iter()
+        .filter(|entity| !ecs.has::<Parent>(*entity))
+        .collect();
+    let mut stack: Vec<(Entity, Affine3A)> = roots.into_iter().map(|root| (root, Affine3A::IDENTITY)).collect();
+    while let Some((entity, parent_matrix)) = stack.pop() {
+        let local = match ecs.get::<Transform>(entity) {
+            Some(transform) => transform.matrix(),
+            None => continue,
+        };
+        let matrix = parent_matrix * local;
+        ecs.insert(entity, GlobalTransform(matrix));
+        for child in children(ecs, entity) {
+            if ecs.is_alive(*child) {
+                stack.push((*child, matrix));
+            };
+        };
+    };
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::FRAC_PI_2;
+
+    fn spawn_at(ecs: &mut Ecs, position: Vec3, rotation: Quat) -> Entity {
+        let entity = ecs.spawn();
+        ecs.insert(entity, Transform {position, rotation});
+        entity
+    }
+
+    fn global(ecs: &Ecs, entity: Entity) -> Vec3 {
+        ecs.get::<GlobalTransform>(entity).unwrap().position()
+    }
+
+    #[test]
+    fn set_parent_keeps_world_transform() {
+        let mut ecs = Ecs::new();
+        let parent = spawn_at(&mut ecs, Vec3::new(5.0, 0.0, -2.0), Quat::from_rotation_y(0.7));
+        let child = spawn_at(&mut ecs, Vec3::new(1.0, 2.0, 3.0), Quat::from_rotation_x(0.3));
+        let before = world_matrix(&ecs, child);
+
+        assert!(set_parent(&mut ecs, child, Some(parent)));
+        assert!(world_matrix(&ecs, child).abs_diff_eq(before, 1e-5));
+        assert_eq!(self::parent(&ecs, child), Some(parent));
+        assert_eq!(children(&ecs, parent), &[child]);
+
+        assert!(set_parent(&mut ecs, child, None));
+        assert!(world_matrix(&ecs, child).abs_diff_eq(before, 1e-5));
+        assert_eq!(self::parent(&ecs, child), None);
+        assert!(children(&ecs, parent).is_empty());
+    }
+
+    #[test]
+    fn rejects_cycles() {
+        let mut ecs = Ecs::new();
+        let a = spawn_at(&mut ecs, Vec3::ZERO, Quat::IDENTITY);
+        let b = spawn_at(&mut ecs, Vec3::X, Quat::IDENTITY);
+        let c = spawn_at(&mut ecs, Vec3::Y, Quat::IDENTITY);
+        assert!(set_parent(&mut ecs, b, Some(a)));
+        assert!(set_parent(&mut ecs, c, Some(b)));
+
+        assert!(!set_parent(&mut ecs, a, Some(a)));
+        assert!(!set_parent(&mut ecs, a, Some(c)));
+        assert_eq!(self::parent(&ecs, a), None);
+        assert_eq!(self::parent(&ecs, c), Some(b));
+    }
+
+    #[test]
+    fn despawns_children() {
+        let mut ecs = Ecs::new();
+        let root = spawn_at(&mut ecs, Vec3::ZERO, Quat::IDENTITY);
+        let a = spawn_at(&mut ecs, Vec3::X, Quat::IDENTITY);
+        let b = spawn_at(&mut ecs, Vec3::Y, Quat::IDENTITY);
+        let grandchild = spawn_at(&mut ecs, Vec3::Z, Quat::IDENTITY);
+        let other = spawn_at(&mut ecs, Vec3::ONE, Quat::IDENTITY);
+        set_parent(&mut ecs, a, Some(root));
+        set_parent(&mut ecs, b, Some(a));
+        set_parent(&mut ecs, grandchild, Some(b));
+        set_parent(&mut ecs, other, Some(root));
+
+        despawn_recursive(&mut ecs, a);
+        for entity in [a, b, grandchild] {
+            assert!(!ecs.is_alive(entity));
+        };
+        assert!(ecs.is_alive(root) && ecs.is_alive(other));
+        assert_eq!(children(&ecs, root), &[other]);
+    }
+
+    #[test]
+    fn propagates_through_tank() {
+        let mut ecs = Ecs::new();
+        let tank = spawn_at(&mut ecs, Vec3::new(10.0, 0.0, 0.0), Quat::from_rotation_y(FRAC_PI_2));
+        let turret = spawn_at(&mut ecs, Vec3::ZERO, Quat::IDENTITY);
+        let barrel = spawn_at(&mut ecs, Vec3::ZERO, Quat::IDENTITY);
+        set_parent(&mut ecs, turret, Some(tank));
+        set_parent(&mut ecs, barrel, Some(turret));
+        ecs.insert(turret, Transform {position: Vec3::new(0.0, -2.0, 0.0), rotation: Quat::from_rotation_y(FRAC_PI_2)});
+        ecs.insert(barrel, Transform {position: Vec3::new(3.0, 0.0, 0.0), rotation: Quat::IDENTITY});
+
+        propagate_transforms(&mut ecs);
+        assert!(global(&ecs, tank).abs_diff_eq(Vec3::new(10.0, 0.0, 0.0), 1e-5));
+        assert!(global(&ecs, turret).abs_diff_eq(Vec3::new(10.0, -2.0, 0.0), 1e-5));
+        // Turned half way round by the tank and the turret, the barrel points down -x
+        assert!(global(&ecs, barrel).abs_diff_eq(Vec3::new(7.0, -2.0, 0.0), 1e-5));
+
+        ecs.get_mut::<Transform>(tank).unwrap().position = Vec3::new(0.0, 0.0, 5.0);
+        propagate_transforms(&mut ecs);
+        assert!(global(&ecs, barrel).abs_diff_eq(Vec3::new(-3.0, -2.0, 5.0), 1e-5));
+        for entity in [tank, turret, barrel] {
+            assert!(ecs.get::<GlobalTransform>(entity).unwrap().0.abs_diff_eq(world_matrix(&ecs, entity), 1e-5));
+        };
+    }
+}
@@ -0,0 +1,507 @@
+use std::{collections::HashMap, fmt, path::{Path, PathBuf}};
+
+use glam::{EulerRot, Quat, Vec3};
+
+use crate::{camera::Camera, color::Color, json::{Json, JsonError}};
+
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(std::io::Error),
+    Json(JsonError),
+    /// The file is valid JSON but doesn't describe a scene
+    Invalid(String),
+    /// A file the scene refers to, like a point cloud, could not be loaded
+    Asset {file: PathBuf, source: Box<dyn std::error::Error + Send + Sync>},
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io(e) => write!(f, "{}", e),
+            SceneError::Json(e) => write!(f, "{}", e),
+            SceneError::Invalid(message) => write!(f, "{}", message),
+            SceneError::Asset {file, source} => write!(f, "{}: {}", file.display(), source),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SceneError::Asset {source, ..} => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for SceneError {
+    fn from(e: std::io::Error) -> Self {
+        SceneError::Io(e)
+    }
+}
+
+impl From<JsonError> for SceneError {
+    fn from(e: JsonError) -> Self {
+        SceneError::Json(e)
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Settings {
+    /// Positive y points down in world space
+    pub gravity: Vec3,
+    /// Whether the camera spins on its own
+    pub auto_rotate: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            gravity: Vec3::new(0.0, 160.0, 0.0),
+            auto_rotate: true,
+        }
+    }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Light {
+    pub direction: Vec3,
+    pub intensity: f32,
+}
+
+impl Default for Light {
+    fn default() -> Self {
+        Light {
+            direction: Vec3::new(-0.5, -0.5, 1.0),
+            intensity: 1.0,
+        }
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Shape {
+    Box {size: Vec3},
+    /// Every object of a baked point cloud file, or the points of a `.ply` file, merged and moved
+    /// so that the file's origin ends up at the object's position
+    Cloud {file: String},
+}
+
+
+/// One object of a scene. Position and rotation are relative to the parent, if it has one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ObjectDesc {
+    /// Lets other objects refer to this one as their parent
+    pub name: Option<String>,
+    pub shape: Shape,
+    pub position: Vec3,
+    pub rotation: Quat,
+    pub color: Color,
+    /// Name of the object this one is attached to
+    pub parent: Option<String>,
+    /// Mass of the rigid body, for objects that fall and get pushed around
+    pub mass: Option<f32>,
+    /// Whether this is the object the player controls
+    pub player: bool,
+    /// Radians per second to turn around the vertical axis
+    pub spin: Option<f32>,
+}
+
+impl ObjectDesc {
+    pub fn new(shape: Shape, position: Vec3, color: Color) -> Self {
+        ObjectDesc {
+            name: None,
+            shape,
+            position,
+            rotation: Quat::IDENTITY,
+            color,
+            parent: None,
+            mass: None,
+            player: false,
+            spin: None,
+        }
+    }
+}
+
+
+/// Everything needed to build a `World`, stored as JSON:
+/// ```text
+/// {
+///   "settings": {"gravity": [0, 160, 0], "auto_rotate": false},
+///   "camera": {"target": [0, 0, 0], "yaw": 0, "pitch": 0.75, "zoom": 1},
+///   "light": {"direction": [-0.5, -0.5, 1], "intensity": 1},
+///   "objects": [
+///     {"name": "tank", "shape": "box", "size": [16, 6, 10], "position": [60, -3.5, -40], "color": [0.3, 0.6, 0.3, 1]},
+///     {"shape": "box", "size": [7, 4, 7], "position": [0, -5, 0], "rotation": [45, 0, 0], "parent": "tank", "spin": -1}
+///   ]
+/// }
+/// ```
+/// Every field is optional, `rotation` is yaw, pitch and roll in degrees. Besides `"box"`, the shape
+/// can be `"cloud"` with a `"file"` holding a baked point cloud or PLY points, relative to the scene file.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SceneFile {
+    pub settings: Settings,
+    pub camera: Camera,
+    pub light: Light,
+    pub objects: Vec<ObjectDesc>,
+    /// Directory the scene was loaded from, files of cloud objects are relative to it
+    pub dir: PathBuf,
+}
+
+impl SceneFile {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SceneError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        let mut scene = SceneFile::parse(&text)?;
+        scene.dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        Ok(scene)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SceneError> {
+        std::fs::write(path, format!("{}\n", self))?;
+        Ok(())
+    }
+
+    pub fn parse(text: &str) -> Result<Self, SceneError> {
+        let json = Json::parse(text)?;
+        let root = Fields::new(&json, "")?;
+        let mut scene = SceneFile::default();
+
+        if let Some(settings) = root.object("settings")? {
+            scene.settings.gravity = settings.vec3("gravity")?.unwrap_or(scene.settings.gravity);
+            scene.settings.auto_rotate = settings.bool("auto_rotate")?.unwrap_or(scene.settings.auto_rotate);
+            settings.finish()?;
+        };
+        if let Some(camera) = root.object("camera")? {
+            scene.camera.target = camera.vec3("target")?.unwrap_or(scene.camera.target);
+            scene.camera.yaw = camera.f32("yaw")?.unwrap_or(scene.camera.yaw);
+            scene.camera.pitch = camera.f32("pitch")?.unwrap_or(scene.camera.pitch);
+            scene.camera.zoom = camera.f32("zoom")?.unwrap_or(scene.camera.zoom);
+            camera.finish()?;
+        };
+        if let Some(light) = root.object("light")? {
+            scene.light.direction = light.vec3("direction")?.unwrap_or(scene.light.direction);
+            scene.light.intensity = light.f32("intensity")?.unwrap_or(scene.light.intensity);
+            light.finish()?;
+        };
+        for (idx, object) in root.array("objects")?.iter().enumerate() {
+            let fields = Fields::new(object, &format!("objects[{}]", idx))?;
+            scene.objects.push(parse_object(&fields)?);
+        };
+        root.finish()?;
+
+        scene.validate()?;
+        Ok(scene)
+    }
+
+    /// Checks that names are unique and that every parent names an object, without going in a
+    /// circle. Typos in parent names are caught here rather than silently dropping the link.
+    pub fn validate(&self) -> Result<(), SceneError> {
+        let mut named = HashMap::new();
+        for (idx, object) in self.objects.iter().enumerate() {
+            if let Some(name) = &object.name {
+                if named.insert(name.as_str(), idx).is_some() {
+                    return Err(SceneError::Invalid(format!("more than one object named \"{}\"", name)));
+                };
+            };
+        };
+        for object in self.objects.iter() {
+            // Any chain longer than the number of objects has to visit one of them twice
+            let mut current = object;
+            for _ in 0..=self.objects.len() {
+                let parent = match &current.parent {
+                    Some(parent) => parent,
+                    None => break,
+                };
+                current = match named.get(parent.as_str()) {
+                    Some(idx) => &self.objects[*idx],
+                    None => return Err(SceneError::Invalid(format!("no object named \"{}\" to attach to", parent))),
+                };
+            };
+            if let Some(parent) = &current.parent {
+                return Err(SceneError::Invalid(format!("\"{}\" is attached to itself through its parents", parent)));
+            };
+        };
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> Json {
+        let settings = Json::Object(vec![
+            ("gravity".to_string(), vec3_json(self.settings.gravity)),
+            ("auto_rotate".to_string(), self.settings.auto_rotate.into()),
+        ]);
+        let camera = Json::Object(vec![
+            ("target".to_string(), vec3_json(self.camera.target)),
+            ("yaw".to_string(), self.camera.yaw.into()),
+            ("pitch".to_string(), self.camera.pitch.into()),
+            ("zoom".to_string(), self.camera.zoom.into()),
+        ]);
+        let light = Json::Object(vec![
+            ("direction".to_string(), vec3_json(self.light.direction)),
+            ("intensity".to_string(), self.light.intensity.into()),
+        ]);
+        Json::Object(vec![
+            ("settings".to_string(), settings),
+            ("camera".to_string(), camera),
+            ("light".to_string(), light),
+            ("objects".to_string(), Json::Array(self.objects.iter().map(object_json).collect())),
+        ])
+    }
+}
+
+impl fmt::Display for SceneFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_json())
+    }
+}
+
+
+fn parse_object(fields: &Fields) -> Result<ObjectDesc, SceneError> {
+    let shape = match fields.str("shape")? {
+        Some("box") | None => Shape::Box {size: fields.vec3("size")?.unwrap_or(Vec3::ONE)},
+        Some("cloud") => match fields.str("file")? {
+            Some(file) => Shape::Cloud {file: file.to_string()},
+            None => return Err(fields.invalid("a cloud needs a \"file\"".to_string())),
+        },
+        Some(other) => return Err(fields.invalid(format!("unknown shape \"{}\"", other))),
+    };
+    let mut object = ObjectDesc::new(
+        shape,
+        fields.vec3("position")?.unwrap_or(Vec3::ZERO),
+        fields.color("color")?.unwrap_or_else(|| Color::rgb(1.0, 1.0, 1.0)),
+    );
+    object.name = fields.str("name")?.map(str::to_string);
+    if let Some(rotation) = fields.vec3("rotation")? {
+        let rotation = rotation * (std::f32::consts::PI / 180.0);
+        object.rotation = Quat::from_euler(EulerRot::YXZ, rotation.x, rotation.y, rotation.z);
+    };
+    object.parent = fields.str("parent")?.map(str::to_string);
+    object.mass = fields.f32("mass")?;
+    object.player = fields.bool("player")?.unwrap_or(false);
+    object.spin = fields.f32("spin")?;
+    fields.finish()?;
+    Ok(object)
+}
+
+fn object_json(object: &ObjectDesc) -> Json {
+    let mut fields = vec![];
+    if let Some(name) = &object.name {
+        fields.push(("name".to_string(), name.as_str().into()));
+    };
+    match &object.shape {
+        Shape::Box {size} => {
+            fields.push(("shape".to_string(), "box".into()));
+            fields.push(("size".to_string(), vec3_json(*size)));
+        },
+        Shape::Cloud {file} => {
+            fields.push(("shape".to_string(), "cloud".into()));
+            fields.push(("file".to_string(), file.as_str().into()));
+        },
+    };
+    fields.push(("position".to_string(), vec3_json(object.position)));
+    if object.rotation != Quat::IDENTITY {
+        let (yaw, pitch, roll) = object.rotation.to_euler(EulerRot::YXZ);
+        fields.push(("rotation".to_string(), vec3_json(Vec3::new(yaw, pitch, roll) * (180.0 / std::f32::consts::PI))));
+    };
+    let c = object.color;
+    fields.push(("color".to_string(), Json::Array(vec![c.r.into(), c.g.into(), c.b.into(), c.a.into()])));
+    if let Some(parent) = &object.parent {
+        fields.push(("parent".to_string(), parent.as_str().into()));
+    };
+    if let Some(mass) = object.mass {
+        fields.push(("mass".to_string(), mass.into()));
+    };
+    if object.player {
+        fields.push(("player".to_string(), true.into()));
+    };
+    if let Some(spin) = object.spin {
+        fields.push(("spin".to_string(), spin.into()));
+    };
+    Json::Object(fields)
+}
+
+fn vec3_json(v: Vec3) -> Json {
+    Json::Array(vec![v.x.into(), v.y.into(), v.z.into()])
+}
+
+
+/// Typed access to the fields of a JSON object, remembering which ones were read so that
+/// unknown (usually misspelled) fields can be reported.
+struct Fields<'a> {
+    path: String,
+    fields: &'a [(String, Json)],
+    used: std::cell::RefCell<Vec<&'a str>>,
+}
+
+impl<'a> Fields<'a> {
+    fn new(json: &'a Json, path: &str) -> Result<Self, SceneError> {
+        let fields = Fields {path: path.to_string(), fields: &[], used: Default::default()};
+        match json.as_object() {
+            Some(object) => Ok(Fields {fields: object, ..fields}),
+            None => Err(fields.invalid("expected an object".to_string())),
+        }
+    }
+
+    fn invalid(&self, message: String) -> SceneError {
+        match self.path.is_empty() {
+            true => SceneError::Invalid(message),
+            false => SceneError::Invalid(format!("{}: {}", self.path, message)),
+        }
+    }
+
+    fn child_path(&self, key: &str) -> String {
+        match self.path.is_empty() {
+            true => key.to_string(),
+            false => format!("{}.{}", self.path, key),
+        }
+    }
+
+    fn get(&self, key: &'a str) -> Option<&'a Json> {
+        self.used.borrow_mut().push(key);
+        self.fields.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    fn finish(&self) -> Result<(), SceneError> {
+        let used = self.used.borrow();
+        match self.fields.iter().find(|(key, _)| !used.contains(&key.as_str())) {
+            Some((key, _)) => Err(self.invalid(format!("unknown field \"{}\"", key))),
+            None => Ok(()),
+        }
+    }
+
+    fn f32(&self, key: &'a str) -> Result<Option<f32>, SceneError> {
+        match self.get(key) {
+            Some(value) => value.as_f32().map(Some).ok_or_else(|| self.invalid(format!("\"{}\" must be a number", key))),
+            None => Ok(None),
+        }
+    }
+
+    fn bool(&self, key: &'a str) -> Result<Option<bool>, SceneError> {
+        match self.get(key) {
+            Some(value) => value.as_bool().map(Some).ok_or_else(|| self.invalid(format!("\"{}\" must be true or false", key))),
+            None => Ok(None),
+        }
+    }
+
+    fn str(&self, key: &'a str) -> Result<Option<&'a str>, SceneError> {
+        match self.get(key) {
+            Some(value) => value.as_str().map(Some).ok_or_else(|| self.invalid(format!("\"{}\" must be a string", key))),
+            None => Ok(None),
+        }
+    }
+
+    fn numbers(&self, key: &'a str, counts: &[usize]) -> Result<Option<Vec<f32>>, SceneError> {
+        let value = match self.get(key) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let numbers: Option<Vec<f32>> = value.as_array()
+            .map(|items| items.iter().map(Json::as_f32).collect())
+            .unwrap_or(None);
+        match numbers {
+            Some(numbers) if counts.contains(&numbers.len()) => Ok(Some(numbers)),
+            _ => {
+                let counts: Vec<String> = counts.iter().map(|count| count.to_string()).collect();
+                Err(self.invalid(format!("\"{}\" must be a list of {} numbers", key, counts.join(" or "))))
+            },
+        }
+    }
+
+    fn vec3(&self, key: &'a str) -> Result<Option<Vec3>, SceneError> {
+        Ok(self.numbers(key, &[3])?.map(|n| Vec3::new(n[0], n[1], n[2])))
+    }
+
+    /// `[r, g, b]` or `[r, g, b, a]`, each `0..=1`.
+    fn color(&self, key: &'a str) -> Result<Option<Color>, SceneError> {
+        Ok(self.numbers(key, &[3, 4])?.map(|n| Color::rgba(n[0], n[1], n[2], n.get(3).copied().unwrap_or(1.0))))
+    }
+
+    fn object(&self, key: &'a str) -> Result<Option<Fields<'a>>, SceneError> {
+        match self.get(key) {
+            Some(value) => Fields::new(value, &self.child_path(key)).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn array(&self, key: &'a str) -> Result<&'a [Json], SceneError> {
+        match self.get(key) {
+            Some(value) => value.as_array().ok_or_else(|| self.invalid(format!("\"{}\" must be a list", key))),
+            None => Ok(&[]),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCENE: &str = r#"{
+        "settings": {"gravity": [0, 100, 0], "auto_rotate": false},
+        "camera": {"target": [1, 2, 3], "yaw": 0.5, "pitch": 0.75, "zoom": 2},
+        "light": {"direction": [-0.5, -0.5, 1], "intensity": 0.8},
+        "objects": [
+            {"name": "tank", "shape": "box", "size": [16, 6, 10], "position": [60, -3.5, -40], "color": [0.3, 0.6, 0.3, 1], "mass": 2},
+            {"shape": "box", "size": [7, 4, 7], "position": [0, -5, 0], "parent": "tank", "spin": -1},
+            {"shape": "cloud", "file": "rock.ply", "position": [5, 0, 5], "player": true}
+        ]
+    }"#;
+
+    fn with_objects(objects: &[(Option<&str>, Option<&str>)]) -> SceneFile {
+        let mut scene = SceneFile::default();
+        for (name, parent) in objects {
+            let mut object = ObjectDesc::new(Shape::Box {size: Vec3::ONE}, Vec3::ZERO, Color::rgb(1.0, 1.0, 1.0));
+            object.name = name.map(str::to_string);
+            object.parent = parent.map(str::to_string);
+            scene.objects.push(object);
+        };
+        scene
+    }
+
+    #[test]
+    fn round_trip() {
+        let scene = SceneFile::parse(SCENE).unwrap();
+        assert_eq!(scene.objects.len(), 3);
+        assert_eq!(scene.objects[1].parent.as_deref(), Some("tank"));
+        assert_eq!(scene.objects[2].shape, Shape::Cloud {file: "rock.ply".to_string()});
+        assert_eq!(SceneFile::parse(&scene.to_string()).unwrap(), scene);
+
+        let default = SceneFile::parse(include_str!("../scenes/default.json")).unwrap();
+        assert_eq!(SceneFile::parse(&default.to_string()).unwrap().to_string(), default.to_string());
+    }
+
+    #[test]
+    fn rotation_in_degrees() {
+        let scene = SceneFile::parse(r#"{"objects": [{"rotation": [90, 0, 0]}]}"#).unwrap();
+        let expected = Quat::from_rotation_y(std::f32::consts::FRAC_PI_2);
+        assert!(scene.objects[0].rotation.abs_diff_eq(expected, 1e-6));
+        let again = SceneFile::parse(&scene.to_string()).unwrap();
+        assert!(again.objects[0].rotation.abs_diff_eq(expected, 1e-6));
+    }
+
+    #[test]
+    fn rejects_bad_objects() {
+        assert!(matches!(SceneFile::parse(r#"{"objects": [{"shape": "sphere"}]}"#), Err(SceneError::Invalid(_))));
+        assert!(matches!(SceneFile::parse(r#"{"objects": [{"szie": [1, 1, 1]}]}"#), Err(SceneError::Invalid(_))));
+        assert!(matches!(SceneFile::parse(r#"{"objects": [{"size": [1, 1]}]}"#), Err(SceneError::Invalid(_))));
+        assert!(matches!(SceneFile::parse(r#"{"objects": [}"#), Err(SceneError::Json(_))));
+    }
+
+    #[test]
+    fn validates_names_and_parents() {
+        assert!(with_objects(&[(Some("a"), None), (Some("b"), Some("a")), (None, Some("b"))]).validate().is_ok());
+        // Duplicate name
+        assert!(with_objects(&[(Some("a"), None), (Some("a"), None)]).validate().is_err());
+        // Unknown parent
+        assert!(with_objects(&[(Some("a"), Some("b"))]).validate().is_err());
+        // Cycles, of one and of three objects
+        assert!(with_objects(&[(Some("a"), Some("a"))]).validate().is_err());
+        assert!(with_objects(&[(Some("a"), Some("c")), (Some("b"), Some("a")), (Some("c"), Some("b")), (None, Some("a"))]).validate().is_err());
+        // Parse runs the same checks
+        let text = r#"{"objects": [{"name": "a", "parent": "b"}, {"name": "b", "parent": "a"}]}"#;
+        assert!(matches!(SceneFile::parse(text), Err(SceneError::Invalid(_))));
+    }
+}
@@ -0,0 +1,20 @@
+use std::{fs::File, io::{self, BufWriter, Write}, path::Path};
+
+
+/// Writes an RGBA frame as a binary PPM image. PPM has no alpha, so it is dropped.
+pub fn write_ppm<W: Write>(writer: &mut W, width: usize, height: usize, rgba: &[u8]) -> io::Result<()> {
+    if rgba.len() != width * height * 4 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "frame size doesn't match the image size"));
+    };
+    write!(writer, "P6\n{} {}\n255\n", width, height)?;
+    for pixel in rgba.chunks_exact(4) {
+        writer.write_all(&pixel[..3])?;
+    };
+    Ok(())
+}
+
+pub fn save_ppm<P: AsRef<Path>>(path: P, width: usize, height: usize, rgba: &[u8]) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_ppm(&mut writer, width, height, rgba)?;
+    writer.flush()
+}
@@ -0,0 +1,49 @@
+use glam::Vec3;
+
+use crate::color::Color;
+
+
+/// A single surface element of a point-based shape.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Surfel {
+    pub pos: Vec3,
+    pub color: Color,
+    pub normal: Vec3,
+    pub material: Option<u16>,
+    /// Emissive surfels ignore the scene light and are drawn at full brightness.
+    pub emissive: bool,
+    pub object_id: Option<u32>,
+}
+
+impl Surfel {
+    pub fn new(pos: Vec3, color: Color, normal: Vec3) -> Self {
+        Surfel {
+            pos,
+            color,
+            normal,
+            material: None,
+            emissive: false,
+            object_id: None,
+        }
+    }
+
+    pub fn with_normal(mut self, normal: Vec3) -> Self {
+        self.normal = normal;
+        self
+    }
+
+    pub fn with_material(mut self, material: u16) -> Self {
+        self.material = Some(material);
+        self
+    }
+
+    pub fn with_emissive(mut self, emissive: bool) -> Self {
+        self.emissive = emissive;
+        self
+    }
+
+    pub fn with_object_id(mut self, object_id: u32) -> Self {
+        self.object_id = Some(object_id);
+        self
+    }
+}
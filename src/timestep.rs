@@ -0,0 +1,95 @@
+use std::time::Instant;
+
+
+/// Frame times above this are clamped so a long stall does not trigger a burst of catch-up ticks.
+const MAX_FRAME_TIME: f32 = 0.25;
+
+
+/// Accumulates real elapsed time and hands it out in fixed size simulation ticks.
+#[derive(Clone, Debug)]
+pub struct FixedTimestep {
+    pub tick_rate: f32,
+    accumulator: f32,
+    last: Instant,
+}
+
+impl FixedTimestep {
+    /// Panics unless `tick_rate` is a positive number of ticks per second.
+    pub fn new(tick_rate: f32) -> Self {
+        assert!(tick_rate > 0.0 && tick_rate.is_finite(), "tick rate must be positive, got {}", tick_rate);
+        FixedTimestep {
+            tick_rate,
+            accumulator: 0.0,
+            last: Instant::now(),
+        }
+    }
+
+    /// Length of one tick in seconds.
+    pub fn dt(&self) -> f32 {
+        1.0 / self.tick_rate
+    }
+
+    /// Adds the real time since the last call and returns how many ticks to simulate now.
+    pub fn advance(&mut self) -> u32 {
+        let now = Instant::now();
+        let elapsed = (now - self.last).as_secs_f32().min(MAX_FRAME_TIME);
+        self.last = now;
+        self.accumulate(elapsed)
+    }
+
+    /// Like `advance`, but with a given amount of time instead of measuring it.
+    pub fn accumulate(&mut self, elapsed: f32) -> u32 {
+        self.accumulator += elapsed;
+        let dt = self.dt();
+        let mut ticks = 0;
+        while self.accumulator >= dt {
+            self.accumulator -= dt;
+            ticks += 1;
+        };
+        ticks
+    }
+
+    /// How far the current frame is between the last tick and the next one, `0..1`.
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator / self.dt()).min(1.0)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accumulate_counts_ticks() {
+        let mut timestep = FixedTimestep::new(4.0);
+        assert_eq!(timestep.dt(), 0.25);
+        assert_eq!(timestep.accumulate(0.1), 0);
+        assert!((timestep.alpha() - 0.4).abs() < 1e-6);
+        assert_eq!(timestep.accumulate(0.5), 2);
+        assert!((timestep.alpha() - 0.4).abs() < 1e-6);
+        assert_eq!(timestep.accumulate(0.15), 1);
+        assert!(timestep.alpha() < 1e-6);
+    }
+
+    #[test]
+    fn alpha_stays_below_one() {
+        let mut timestep = FixedTimestep::new(25.0);
+        for _ in 0..100 {
+            timestep.accumulate(0.013);
+            assert!((0.0..1.0).contains(&timestep.alpha()));
+        };
+    }
+
+    #[test]
+    #[should_panic]
+    fn zero_tick_rate() {
+        FixedTimestep::new(0.0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn negative_tick_rate() {
+        FixedTimestep::new(-25.0);
+    }
+}
@@ -0,0 +1,129 @@
+/// How the render target relates to the window.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScaleMode {
+    /// The resolution stays as set. `pixels` draws the image at the largest whole multiple that
+    /// fits the window, with black bars around it.
+    Fixed,
+    /// The resolution follows the window, each render pixel covering `pixel_scale` window pixels.
+    /// Whatever is left over after dividing the window goes to the bars.
+    Fit,
+}
+
+
+/// The pixel scale is kept at or below this, so the render target stays a sensible size
+pub const MAX_PIXEL_SCALE: u32 = 16;
+
+
+/// Size of the render target and how it is scaled up to the window.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Viewport {
+    pub width: u32,
+    pub height: u32,
+    pub mode: ScaleMode,
+    /// Window pixels per render pixel, used by `ScaleMode::Fit` and when changing the scale
+    pub pixel_scale: u32,
+    /// Last known window size in physical pixels
+    pub window: (u32, u32),
+}
+
+impl Viewport {
+    pub fn new(width: u32, height: u32, pixel_scale: u32, mode: ScaleMode) -> Self {
+        let pixel_scale = pixel_scale.clamp(1, MAX_PIXEL_SCALE);
+        Viewport {
+            width,
+            height,
+            mode,
+            pixel_scale,
+            window: (width * pixel_scale, height * pixel_scale),
+        }
+    }
+
+    /// Resolution that fills the window at the current pixel scale.
+    pub fn fit_resolution(&self) -> (u32, u32) {
+        (
+            (self.window.0 / self.pixel_scale).max(1),
+            (self.window.1 / self.pixel_scale).max(1),
+        )
+    }
+
+    /// Changes the render target size. Returns whether it is different from before, in which
+    /// case the frame buffers need to be reallocated.
+    pub fn set_resolution(&mut self, width: u32, height: u32) -> bool {
+        let (width, height) = (width.max(1), height.max(1));
+        let changed = (width, height) != (self.width, self.height);
+        self.width = width;
+        self.height = height;
+        changed
+    }
+
+    /// To be called when the window changes size. Returns whether the resolution changed.
+    pub fn window_resized(&mut self, width: u32, height: u32) -> bool {
+        self.window = (width, height);
+        match self.mode {
+            ScaleMode::Fixed => false,
+            ScaleMode::Fit => {
+                let (width, height) = self.fit_resolution();
+                self.set_resolution(width, height)
+            },
+        }
+    }
+
+    /// Switches to `mode`. Entering `ScaleMode::Fit` refits the resolution to the window right
+    /// away, returns whether that changed it.
+    pub fn set_mode(&mut self, mode: ScaleMode) -> bool {
+        self.mode = mode;
+        let window = self.window;
+        self.window_resized(window.0, window.1)
+    }
+
+    /// Changes the pixel scale by `steps` and refits the resolution to the window, in either
+    /// mode. Returns whether the resolution changed.
+    pub fn change_pixel_scale(&mut self, steps: i32) -> bool {
+        self.pixel_scale = (self.pixel_scale as i32 + steps).clamp(1, MAX_PIXEL_SCALE as i32) as u32;
+        let (width, height) = self.fit_resolution();
+        self.set_resolution(width, height)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fit_follows_window() {
+        let mut viewport = Viewport::new(300, 200, 3, ScaleMode::Fit);
+        assert_eq!(viewport.window, (900, 600));
+        assert!(!viewport.window_resized(900, 600));
+        assert!(viewport.window_resized(1000, 601));
+        assert_eq!((viewport.width, viewport.height), (333, 200));
+        // Never smaller than a pixel
+        assert!(viewport.window_resized(1, 0));
+        assert_eq!((viewport.width, viewport.height), (1, 1));
+    }
+
+    #[test]
+    fn fixed_keeps_resolution() {
+        let mut viewport = Viewport::new(300, 200, 2, ScaleMode::Fixed);
+        assert!(!viewport.window_resized(1920, 1080));
+        assert_eq!((viewport.width, viewport.height), (300, 200));
+        assert_eq!(viewport.fit_resolution(), (960, 540));
+
+        assert!(viewport.set_mode(ScaleMode::Fit));
+        assert_eq!((viewport.width, viewport.height), (960, 540));
+        assert!(!viewport.set_mode(ScaleMode::Fixed));
+        assert!(!viewport.set_mode(ScaleMode::Fit));
+    }
+
+    #[test]
+    fn pixel_scale_is_clamped() {
+        let mut viewport = Viewport::new(100, 100, 0, ScaleMode::Fixed);
+        assert_eq!(viewport.pixel_scale, 1);
+        assert!(!viewport.change_pixel_scale(-1));
+        assert!(viewport.change_pixel_scale(1));
+        assert_eq!((viewport.width, viewport.height), (50, 50));
+        assert!(viewport.change_pixel_scale(100));
+        assert_eq!(viewport.pixel_scale, MAX_PIXEL_SCALE);
+        assert_eq!(viewport.width, 100 / MAX_PIXEL_SCALE);
+    }
+}
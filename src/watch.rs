@@ -0,0 +1,49 @@
+use std::{path::{Path, PathBuf}, time::{Duration, Instant, SystemTime}};
+
+
+/// Notices changes to a file by polling its modification time and size.
+#[derive(Clone, Debug)]
+pub struct FileWatcher {
+    pub path: PathBuf,
+    /// Minimum time between two looks at the file
+    pub interval: Duration,
+    last_check: Instant,
+    /// Modification time and size as of the last look, `None` while the file doesn't exist
+    stamp: Option<(SystemTime, u64)>,
+}
+
+impl FileWatcher {
+    pub fn new<P: AsRef<Path>>(path: P, interval: Duration) -> Self {
+        let mut watcher = FileWatcher {
+            path: path.as_ref().to_path_buf(),
+            interval,
+            last_check: Instant::now(),
+            stamp: None,
+        };
+        watcher.stamp = watcher.read_stamp();
+        watcher
+    }
+
+    fn read_stamp(&self) -> Option<(SystemTime, u64)> {
+        let metadata = std::fs::metadata(&self.path).ok()?;
+        Some((metadata.modified().ok()?, metadata.len()))
+    }
+
+    /// Whether the file was written since the last call. Cheap to call every frame, the file
+    /// is only looked at once per `interval`. A file being deleted does not count as a change.
+    pub fn changed(&mut self) -> bool {
+        if self.last_check.elapsed() < self.interval {
+            return false;
+        };
+        self.last_check = Instant::now();
+        let stamp = self.read_stamp();
+        let changed = stamp.is_some() && stamp != self.stamp;
+        self.stamp = stamp;
+        changed
+    }
+
+    /// Takes the file as it is now as seen, e.g. after writing it ourselves.
+    pub fn mark_seen(&mut self) {
+        self.stamp = self.read_stamp();
+    }
+}
@@ -0,0 +1,467 @@
+use std::collections::{HashMap, HashSet};
+
+use glam::{Vec2, Vec3, Quat};
+
+use crate::{drawable::Drawable, components::{self, Renderable, Script, Transform}, ecs::{Ecs, Entity, System}, scene, camera::{Camera, CameraController}, input::Actions, boxshape::BoxShape, bvh::{Bvh, ProxyId}, frustum::Frustum, physics::{Physics, RigidBody}, picking::{self, PickHit, NO_OBJECT}, player::{FollowCamera, Player}, ply, pointcloud::{self, PointCloud}, raster::{Batch, Positions, Renderer, View}, scenefile::{Light, ObjectDesc, SceneError, SceneFile, Settings, Shape}};
+
+
+/// Turns an entity around the vertical axis at `speed` radians per second.
+fn spin(speed: f32) -> Script {
+    Script::new(move |entity, ecs, dt| {
+        if let Some(transform) = ecs.get_mut::<Transform>(entity) {
+            transform.rotation = Quat::from_rotation_y(speed * dt) * transform.rotation;
+        };
+    })
+}
+
+
+pub struct World {
+    /// Geometry of every entity with a `Renderable`
+    pub objects: Vec<Box<dyn Drawable>>,
+    pub ecs: Ecs,
+    /// Run in order at the start of every tick
+    pub systems: Vec<System>,
+    pub light_dir: Vec3,
+    pub light_intensity: f32,
+    pub camera: Camera,
+    pub camera_controller: CameraController,
+    pub width: usize,
+    pub height: usize,
+    pub time: f32,
+    /// Simulation state as of the previous tick, for interpolating between ticks when drawing
+    prev_camera: Camera,
+    prev_origins: Vec<Vec3>,
+    /// How far between the previous and the current tick to draw, `0..=1`
+    pub render_alpha: f32,
+    pub paused: bool,
+    /// Spatial index over object bounds, holding indices into `objects`
+    pub index: Bvh<usize>,
+    index_proxies: Vec<(ProxyId, u64)>,
+    pub selected: Option<usize>,
+    /// Object id of the surfel that ended up in each pixel, `NO_OBJECT` where nothing was drawn.
+    /// Only filled while enabled with `set_id_buffer_enabled`.
+    pub id_buffer: Option<Vec<u32>>,
+    pub physics: Physics,
+    pub player: Option<Player>,
+    pub follow_camera: FollowCamera,
+    /// Draws the points, keeping what it can from one frame to the next
+    pub renderer: Renderer,
+    /// Point positions of each object in the layout `draw` transforms them in, with the object
+    /// revision they were taken at
+    positions: Vec<(u64, Positions)>,
+}
+
+impl World {
+    /// An empty world, see `from_scene` for filling it.
+    pub fn new(width: usize, height: usize) -> World {
+        let mut world = World {
+            objects: vec![],
+            ecs: Ecs::new(),
+            systems: vec![components::run_scripts, components::integrate_velocities],
+            light_dir: Vec3::new(-0.5, -0.5, 1.0),
+            light_intensity: 1.0,
+            camera: Camera::default(),
+            camera_controller: CameraController::default(),
+            width,
+            height,
+            time: 0.0,
+            prev_camera: Camera::default(),
+            prev_origins: vec![],
+            render_alpha: 1.0,
+            paused: false,
+            index: Bvh::new(),
+            index_proxies: vec![],
+            selected: None,
+            id_buffer: None,
+            physics: Physics::new(),
+            player: None,
+            follow_camera: FollowCamera::default(),
+            renderer: Renderer::new(std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1)),
+            positions: vec![],
+        };
+        world.sync_index();
+        world
+    }
+
+    /// Builds the objects of `scene`. Fails if the scene doesn't pass `SceneFile::validate` or one of
+    /// its cloud files can't be loaded.
+    pub fn from_scene(scene: &SceneFile, width: usize, height: usize) -> Result<World, SceneError> {
+        scene.validate()?;
+        let mut world = World::new(width, height);
+        world.physics.gravity = scene.settings.gravity;
+        world.camera_controller.auto_rotate = scene.settings.auto_rotate;
+        world.camera = scene.camera;
+        world.prev_camera = scene.camera;
+        world.light_dir = scene.light.direction;
+        world.light_intensity = scene.light.intensity;
+
+        let mut named = HashMap::new();
+        let mut entities = vec![];
+        for desc in scene.objects.iter() {
+            let object: Box<dyn Drawable> = match &desc.shape {
+                Shape::Box {size} => Box::new(BoxShape::new(desc.position, *size, desc.color)),
+                Shape::Cloud {file} => {
+                    let path = scene.dir.join(file);
+                    let loaded = match file.ends_with(".ply") {
+                        true => ply::load(&path).map_err(|e| e.into()),
+                        false => pointcloud::load(&path)
+                            .map(|objects| PointCloud::merged(objects, Vec3::ZERO))
+                            .map_err(|e| e.into()),
+                    };
+                    let mut cloud = loaded.map_err(|source| SceneError::Asset {file: path, source})?;
+                    cloud.set_origin(desc.position);
+                    Box::new(cloud)
+                },
+            };
+            let object_idx = world.objects.len();
+            let entity = world.spawn_object(object);
+            world.ecs.insert(entity, Transform::new(desc.position).with_rotation(desc.rotation));
+            if let Some(speed) = desc.spin {
+                world.ecs.insert(entity, spin(speed));
+            };
+            if desc.player {
+                let player = Player::new(object_idx);
+                world.physics.add_body(player.body(desc.mass.unwrap_or(1.0)));
+                world.player = Some(player);
+            } else if let Some(mass) = desc.mass {
+                world.physics.add_body(RigidBody::new(object_idx, mass));
+            };
+            if let Some(name) = &desc.name {
+                named.insert(name.clone(), entity);
+            };
+            world.ecs.insert(entity, desc.clone());
+            entities.push(entity);
+        };
+
+        // Positions in the file are already relative to the parent
+        for (desc, entity) in scene.objects.iter().zip(entities) {
+            if let Some(name) = &desc.parent {
+                let local = world.ecs.get::<Transform>(entity).copied().unwrap_or_default();
+                let attached = named.get(name).map(|parent| scene::set_parent(&mut world.ecs, entity, Some(*parent)));
+                if attached != Some(true) {
+                    return Err(SceneError::Invalid(format!("can't attach to \"{}\"", name)));
+                };
+                world.ecs.insert(entity, local);
+            };
+        };
+        scene::propagate_transforms(&mut world.ecs);
+        components::push_transforms(&world.ecs, &mut world.objects);
+        world.sync_index();
+        Ok(world)
+    }
+
+    /// Replaces everything in the world with `scene`, except for how it is being looked at. The
+    /// camera pose stays, settings such as `auto_rotate` come from the new scene. On error the
+    /// world is left as it was.
+    pub fn reload_scene(&mut self, scene: &SceneFile) -> Result<(), SceneError> {
+        let mut fresh = World::from_scene(scene, self.width, self.height)?;
+        fresh.camera = self.camera;
+        fresh.prev_camera = self.prev_camera;
+        fresh.follow_camera = self.follow_camera;
+        fresh.render_alpha = self.render_alpha;
+        fresh.paused = self.paused;
+        fresh.set_id_buffer_enabled(self.id_buffer.is_some());
+        fresh.renderer.threads = self.renderer.threads;
+        *self = fresh;
+        Ok(())
+    }
+
+    /// The world as it is now, for saving. Only objects that came from a scene are included.
+    pub fn to_scene(&self) -> SceneFile {
+        let mut scene = SceneFile {
+            settings: Settings {
+                gravity: self.physics.gravity,
+                auto_rotate: self.camera_controller.auto_rotate,
+            },
+            camera: self.camera,
+            light: Light {
+                direction: self.light_dir,
+                intensity: self.light_intensity,
+            },
+            objects: vec![],
+            dir: Default::default(),
+        };
+
+        let mut entities = self.ecs.with::<ObjectDesc>();
+        entities.sort_unstable();
+        let mut names: HashMap<Entity, String> = entities.iter()
+            .filter_map(|entity| self.ecs.get::<ObjectDesc>(*entity).and_then(|desc| desc.name.clone()).map(|name| (*entity, name)))
+            .collect();
+        let mut taken: HashSet<String> = names.values().cloned().collect();
+        // Parents need a name to be referred to, one that no other object has
+        for entity in entities.iter() {
+            let parent = match scene::parent(&self.ecs, *entity) {
+                Some(parent) if self.ecs.has::<ObjectDesc>(parent) && !names.contains_key(&parent) => parent,
+                _ => continue,
+            };
+            let name = (0..)
+                .map(|n| match n {
+                    0 => format!("object{}", parent.index),
+                    n => format!("object{}_{}", parent.index, n),
+                })
+                .find(|name| !taken.contains(name))
+                .unwrap_or_default();
+            taken.insert(name.clone());
+            names.insert(parent, name);
+        };
+
+        for entity in entities {
+            let mut desc = match self.ecs.get::<ObjectDesc>(entity) {
+                Some(desc) => desc.clone(),
+                None => continue,
+            };
+            desc.name = names.get(&entity).cloned();
+            if let Some(transform) = self.ecs.get::<Transform>(entity) {
+                desc.position = transform.position;
+                desc.rotation = transform.rotation;
+            };
+            desc.parent = scene::parent(&self.ecs, entity).and_then(|parent| names.get(&parent).cloned());
+            if let Some(object) = self.ecs.get::<Renderable>(entity).map(|renderable| renderable.object) {
+                desc.player = self.player.map(|player| player.object) == Some(object);
+                desc.mass = self.physics.body_for(object).map(|body| body.mass);
+            };
+            scene.objects.push(desc);
+        };
+        scene
+    }
+
+    /// Adds a drawable along with an entity that renders it, placed by its origin. It collides
+    /// through `Drawable::get_collider`, which `Physics` reads every step.
+    pub fn spawn_object(&mut self, object: Box<dyn Drawable>) -> Entity {
+        let entity = self.ecs.spawn();
+        let origin = object.get_origin();
+        self.ecs.insert(entity, Transform::new(origin));
+        self.ecs.insert(entity, Renderable {object: self.objects.len()});
+        self.objects.push(object);
+        entity
+    }
+
+    /// Brings `index` in line with `objects`. Objects are expected to only ever be appended or
+    /// popped, as the index refers to them by position.
+    pub fn sync_index(&mut self) {
+        while self.index_proxies.len() > self.objects.len() {
+            if let Some((proxy, _)) = self.index_proxies.pop() {
+                self.index.remove(proxy);
+            };
+        };
+        for (idx, object) in self.objects.iter().enumerate() {
+            match self.index_proxies.get_mut(idx) {
+                Some((proxy, revision)) => {
+                    if *revision == 0 || *revision != object.revision() {
+                        self.index.update(*proxy, object.get_bounds());
+                        *revision = object.revision();
+                    };
+                },
+                None => {
+                    let proxy = self.index.insert(object.get_bounds(), idx);
+                    self.index_proxies.push((proxy, object.revision()));
+                },
+            };
+        };
+    }
+
+    /// Changes the size of the render target. The frame passed to `draw` has to match it.
+    pub fn resize(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.height = height;
+        if let Some(ids) = self.id_buffer.as_mut() {
+            ids.clear();
+            ids.resize(width * height, NO_OBJECT);
+        };
+    }
+
+    pub fn set_id_buffer_enabled(&mut self, enabled: bool) {
+        self.id_buffer = match enabled {
+            true => Some(vec![NO_OBJECT; self.width * self.height]),
+            false => None,
+        };
+    }
+
+    /// Id of the object drawn at the given pixel during the last `draw`, if the id buffer is enabled.
+    pub fn object_at(&self, x: usize, y: usize) -> Option<u32> {
+        let ids = self.id_buffer.as_ref()?;
+        let id = ids[self.grid_idx(x, y)?];
+        match id == NO_OBJECT {
+            true => None,
+            false => Some(id),
+        }
+    }
+
+    fn grid_idx<I: std::convert::TryInto<usize>>(&self, x: I, y: I) -> Option<usize> {
+        if let (Ok(x), Ok(y)) = (x.try_into(), y.try_into()) {
+            if x < self.width && y < self.height {
+                Some(x + y * self.width)
+            } else {
+                None
+            }
+        } else {
+            None
+        }
+    }
+
+    fn screen_center(&self) -> Vec2 {
+        Vec2::new(self.width as f32, self.height as f32) * 0.5
+    }
+
+    /// `camera` interpolated between the previous and the current tick.
+    pub fn render_camera(&self) -> Camera {
+        self.prev_camera.lerp(&self.camera, self.render_alpha)
+    }
+
+    /// World space volume that ends up on screen, matching `View::pixel`.
+    pub fn frustum(&self) -> Frustum {
+        self.render_camera().frustum(
+            self.screen_center(),
+            Vec2::new(-1.0, -1.0),
+            Vec2::new(self.width as f32, self.height as f32),
+        )
+    }
+
+    /// Object under the given pixel of the render target, and where it was hit in world space.
+    pub fn pick(&self, screen: Vec2) -> Option<PickHit> {
+        let scene_bounds = self.index.root_bounds().unwrap_or_default();
+        let ray = self.render_camera().screen_ray(screen, self.screen_center(), &scene_bounds);
+        picking::pick(&self.index, &ray)
+    }
+
+    /// How far to shift an object from its current position to where it is drawn this frame.
+    fn render_offset(&self, object_idx: usize) -> Vec3 {
+        match self.prev_origins.get(object_idx) {
+            Some(prev) => {
+                let current = self.objects[object_idx].get_origin();
+                (*prev - current) * (1.0 - self.render_alpha)
+            },
+            None => Vec3::ZERO,
+        }
+    }
+
+    /// Advances the simulation by one tick of `dt` seconds.
+    pub fn update(&mut self, dt: f32, actions: &Actions) {
+        self.prev_camera = self.camera;
+        self.prev_origins = self.objects.iter().map(|object| object.get_origin()).collect();
+
+        // The camera keeps working while paused, to look around a frozen scene
+        self.camera_controller.update(&mut self.camera, actions, dt);
+
+        if actions.pressed("pause") {
+            self.paused = !self.paused;
+        };
+        if self.paused {
+            return;
+        };
+
+        self.time += dt;
+        for system in self.systems.iter() {
+            system(&mut self.ecs, dt);
+        };
+        scene::propagate_transforms(&mut self.ecs);
+        components::push_transforms(&self.ecs, &mut self.objects);
+        for object in self.objects.iter_mut() {
+            object.update(dt);
+        };
+        if let Some(player) = self.player.as_mut() {
+            player.update(actions, &self.camera, &mut self.physics, dt);
+        };
+        self.physics.step(dt, &mut self.objects);
+        components::pull_transforms(&mut self.ecs, &self.objects);
+        if let Some(player) = self.player {
+            if let Some(object) = self.objects.get(player.object) {
+                self.follow_camera.update(&mut self.camera, object.get_origin(), dt);
+            };
+        };
+        self.sync_index();
+    }
+
+    pub fn draw(&mut self, screen: &mut [u8]) {
+        // Taken out for the duration of the draw so it can be written while `self` is borrowed
+        let mut id_buffer = self.id_buffer.take();
+        if let Some(ids) = id_buffer.as_mut() {
+            ids.resize(self.width * self.height, NO_OBJECT);
+        };
+
+        // Only visit objects that are on screen, before touching any of their points
+        let frustum = self.frustum();
+        let mut visible = self.index.query_frustum(&frustum);
+        visible.retain(|idx| *idx < self.objects.len());
+        // Objects added since the last `sync_index` are not in the index yet
+        visible.extend(
+            (self.index_proxies.len()..self.objects.len())
+                .filter(|idx| frustum.intersects_aabb(&self.objects[*idx].get_bounds()))
+        );
+        visible.sort_unstable();
+
+        // Rebuild the position arrays of objects that changed since the last draw
+        self.positions.truncate(self.objects.len());
+        for (idx, object) in self.objects.iter().enumerate() {
+            match self.positions.get_mut(idx) {
+                Some((revision, _)) if *revision != 0 && *revision == object.revision() => (),
+                Some(entry) => *entry = (object.revision(), Positions::from_surfels(object.get_points())),
+                None => self.positions.push((object.revision(), Positions::from_surfels(object.get_points()))),
+            };
+        };
+
+        let offsets: Vec<Vec3> = visible.iter().map(|object_idx| self.render_offset(*object_idx)).collect();
+        let batches: Vec<Batch> = visible.into_iter()
+            .zip(offsets)
+            .map(|(object_idx, offset)| Batch {
+                points: self.objects[object_idx].get_points(),
+                positions: &self.positions[object_idx].1,
+                revision: self.objects[object_idx].revision(),
+                offset,
+                object_id: object_idx as u32,
+            })
+            .collect();
+        let view = View::new(&self.render_camera(), self.width, self.height, self.light_dir, self.light_intensity);
+        self.renderer.draw(&view, &batches, screen, id_buffer.as_deref_mut());
+        self.id_buffer = id_buffer;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+
+    #[test]
+    fn to_scene_names_parents_without_touching_world() {
+        let mut world = World::new(32, 32);
+        let spawn = |world: &mut World, name: Option<String>| {
+            let desc = ObjectDesc {name, ..ObjectDesc::new(Shape::Box {size: Vec3::ONE}, Vec3::ZERO, Color::rgb(1.0, 1.0, 1.0))};
+            let entity = world.spawn_object(Box::new(BoxShape::new(desc.position, Vec3::ONE, desc.color)));
+            world.ecs.insert(entity, desc);
+            entity
+        };
+        let parent = spawn(&mut world, None);
+        let child = spawn(&mut world, None);
+        spawn(&mut world, Some(format!("object{}", parent.index)));
+        scene::set_parent(&mut world.ecs, child, Some(parent));
+
+        let scene = world.to_scene();
+        assert!(scene.validate().is_ok());
+        let name = scene.objects[0].name.clone();
+        assert_eq!(name, Some(format!("object{}_1", parent.index)));
+        assert_eq!(scene.objects[1].parent, name);
+        assert_eq!(scene.objects[1].name, None);
+        assert_eq!(world.ecs.get::<ObjectDesc>(parent).unwrap().name, None);
+        assert_eq!(world.to_scene(), scene);
+    }
+
+    #[test]
+    fn loads_clouds_next_to_scene() {
+        let dir = std::env::temp_dir().join(format!("topdown-world-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("clouds")).unwrap();
+        let rock: Vec<Box<dyn Drawable>> = vec![Box::new(BoxShape::new(Vec3::ZERO, Vec3::splat(4.0), Color::rgb(0.5, 0.5, 0.5)))];
+        pointcloud::save(dir.join("clouds/rock.pcl"), &rock).unwrap();
+        let text = r#"{"objects": [{"shape": "cloud", "file": "clouds/rock.pcl", "position": [5, 0, 0]}]}"#;
+        std::fs::write(dir.join("scene.json"), text).unwrap();
+        std::fs::write(dir.join("missing.json"), text.replace("rock", "pebble")).unwrap();
+
+        let world = World::from_scene(&SceneFile::load(dir.join("scene.json")).unwrap(), 32, 32).unwrap();
+        assert_eq!(world.objects[0].get_points().len(), rock[0].get_points().len());
+        assert_eq!(world.objects[0].get_origin(), Vec3::new(5.0, 0.0, 0.0));
+        let missing = World::from_scene(&SceneFile::load(dir.join("missing.json")).unwrap(), 32, 32);
+        assert!(matches!(missing, Err(SceneError::Asset {file, ..}) if file == dir.join("clouds/pebble.pcl")));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}